@@ -0,0 +1,359 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::io::ErrorKind;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// User and groups to run the program as
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    uid: u32,
+    gid: u32,
+    /// Supplementary groups, the current ones are kept if not set
+    groups: Option<Vec<u32>>,
+    /// Name and home directory of the user, if it is switched
+    user: Option<(String, String)>,
+}
+
+/// Entry of `/etc/passwd`: name, uid, gid and home directory
+///
+struct Passwd {
+    name: String,
+    uid: u32,
+    gid: u32,
+    home: String,
+}
+
+impl Account {
+    /// Resolve the account from the user and group names or ids of the config.
+    /// The group defaults to the primary group of the user, and the supplementary
+    /// groups to the ones the user is a member of. Fails on the nonexistent ones.
+    ///
+    pub fn resolve(user: Option<&str>, group: Option<&str>, groups: &[String]) -> io::Result<Self> {
+        let passwd = user.map(find_user).transpose()?;
+        let gid = match (group, passwd.as_ref()) {
+            (Some(group), _) => find_group(group)?,
+            (None, Some(passwd)) => passwd.gid,
+            (None, None) => unsafe { libc::getegid() },
+        };
+        let groups = match (groups, passwd.as_ref()) {
+            ([], Some(passwd)) => Some(get_group_list(&passwd.name, passwd.gid)?),
+            ([], None) => None,
+            (groups, _) => Some(
+                groups
+                    .iter()
+                    .map(|group| find_group(group))
+                    .collect::<io::Result<_>>()?,
+            ),
+        };
+        Ok(Account {
+            uid: passwd
+                .as_ref()
+                .map_or_else(|| unsafe { libc::geteuid() }, |passwd| passwd.uid),
+            gid,
+            groups,
+            user: passwd.map(|passwd| (passwd.name, passwd.home)),
+        })
+    }
+
+    /// Check if the tray runs as this account already, so there is nothing to switch.
+    ///
+    pub fn is_current(&self) -> bool {
+        let same_groups = match self.groups.as_ref() {
+            Some(groups) => get_groups().is_ok_and(|mut current| {
+                let mut groups = groups.clone();
+                groups.sort_unstable();
+                current.sort_unstable();
+                groups.dedup();
+                current.dedup();
+                groups == current
+            }),
+            None => true,
+        };
+        let (euid, egid) = unsafe { (libc::geteuid(), libc::getegid()) };
+        self.uid == euid && self.gid == egid && same_groups
+    }
+
+    /// Check if the tray may switch to the account without the superuser rights.
+    ///
+    pub fn can_switch(&self) -> bool {
+        let euid = unsafe { libc::geteuid() };
+        euid == 0 || self.is_current()
+    }
+
+    /// Setup the command to run as the account, which requires the superuser rights.
+    ///
+    pub fn apply(&self, command: &mut Command) {
+        if self.is_current() {
+            return;
+        }
+        for (name, value) in self.get_user_env() {
+            command.env(name, value);
+        }
+        let (uid, gid, groups) = (self.uid, self.gid, self.groups.clone());
+        unsafe {
+            command.pre_exec(move || {
+                if let Some(groups) = groups.as_ref() {
+                    check(libc::setgroups(groups.len(), groups.as_ptr()))?;
+                }
+                check(libc::setgid(gid))?;
+                check(libc::setuid(uid))
+            });
+        }
+    }
+
+    /// Command line running the program as the account from the superuser one.
+    ///
+    pub fn wrap(&self, argv: &[String]) -> Vec<String> {
+        let groups = match self.groups.as_ref() {
+            Some(groups) if groups.is_empty() => "--clear-groups".to_string(),
+            Some(groups) => {
+                let ids: Vec<String> = groups.iter().map(u32::to_string).collect();
+                format!("--groups={}", ids.join(","))
+            }
+            None => "--keep-groups".to_string(),
+        };
+        let mut command = vec![
+            "setpriv".to_string(),
+            format!("--reuid={}", self.uid),
+            format!("--regid={}", self.gid),
+            groups,
+            "--".to_string(),
+        ];
+        let env = self.get_user_env();
+        if !env.is_empty() {
+            command.push("env".to_string());
+            command.extend(
+                env.iter()
+                    .map(|(name, value)| format!("{}={}", name, value)),
+            );
+        }
+        command.extend(argv.iter().cloned());
+        command
+    }
+
+    /// Variables telling the program its user, if it is switched.
+    ///
+    fn get_user_env(&self) -> Vec<(&'static str, String)> {
+        match self.user.as_ref() {
+            Some((name, home)) => vec![
+                ("HOME", home.clone()),
+                ("USER", name.clone()),
+                ("LOGNAME", name.clone()),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Find the user by name or id.
+///
+fn find_user(user: &str) -> io::Result<Passwd> {
+    let name = CString::new(user)?;
+    let uid = user.parse::<libc::uid_t>().ok();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    loop {
+        let mut entry: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let error = unsafe {
+            match uid {
+                Some(uid) => libc::getpwuid_r(
+                    uid,
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                ),
+                None => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                ),
+            }
+        };
+        match error {
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if result.is_null() => {
+                let msg = format!("user '{}' does not exist", user);
+                return Err(io::Error::new(ErrorKind::NotFound, msg));
+            }
+            0 => {
+                let text = |ptr| {
+                    unsafe { CStr::from_ptr(ptr) }
+                        .to_string_lossy()
+                        .into_owned()
+                };
+                return Ok(Passwd {
+                    name: text(entry.pw_name),
+                    uid: entry.pw_uid,
+                    gid: entry.pw_gid,
+                    home: text(entry.pw_dir),
+                });
+            }
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+}
+
+/// Find the group id by name or id.
+///
+fn find_group(group: &str) -> io::Result<u32> {
+    let name = CString::new(group)?;
+    let gid = group.parse::<libc::gid_t>().ok();
+    let mut buffer = vec![0 as libc::c_char; 4096];
+    loop {
+        let mut entry: libc::group = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let error = unsafe {
+            match gid {
+                Some(gid) => libc::getgrgid_r(
+                    gid,
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                ),
+                None => libc::getgrnam_r(
+                    name.as_ptr(),
+                    &mut entry,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                ),
+            }
+        };
+        match error {
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            0 if result.is_null() => {
+                let msg = format!("group '{}' does not exist", group);
+                return Err(io::Error::new(ErrorKind::NotFound, msg));
+            }
+            0 => return Ok(entry.gr_gid),
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+}
+
+/// Groups the user is a member of, as on login.
+///
+fn get_group_list(user: &str, gid: u32) -> io::Result<Vec<u32>> {
+    let name = CString::new(user)?;
+    let mut groups = vec![0 as libc::gid_t; 32];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let found =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if found >= 0 {
+            groups.truncate(count as usize);
+            return Ok(groups);
+        }
+        groups.resize((count as usize).max(groups.len() * 2), 0);
+    }
+}
+
+/// Supplementary groups of the tray.
+///
+fn get_groups() -> io::Result<Vec<u32>> {
+    let count = check_count(unsafe { libc::getgroups(0, std::ptr::null_mut()) })?;
+    let mut groups = vec![0 as libc::gid_t; count];
+    let count = check_count(unsafe { libc::getgroups(count as libc::c_int, groups.as_mut_ptr()) })?;
+    groups.truncate(count);
+    Ok(groups)
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+fn check_count(result: libc::c_int) -> io::Result<usize> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        count => Ok(count as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolve_accounts() -> io::Result<()> {
+        let root = Account::resolve(Some("root"), None, &[])?;
+        assert_eq!((root.uid, root.gid), (0, 0));
+        assert!(root.groups.as_ref().unwrap().contains(&0));
+        assert_eq!(Account::resolve(Some("0"), Some("root"), &[])?, root);
+
+        let account = Account::resolve(Some("root"), Some("0"), &["0".to_string()])?;
+        assert_eq!(account.groups, Some(vec![0]));
+        let account = Account::resolve(None, Some("0"), &[])?;
+        assert_eq!(account.uid, unsafe { libc::geteuid() });
+        assert_eq!(account.groups, None);
+        assert_eq!(account.user, None);
+
+        let current = unsafe { libc::getegid() }.to_string();
+        assert!(Account::resolve(None, Some(&current), &[])?.is_current());
+
+        let res = Account::resolve(Some("no-such-user"), None, &[]);
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "user 'no-such-user' does not exist"
+        );
+        let res = Account::resolve(
+            None,
+            None,
+            &["root".to_string(), "no-such-group".to_string()],
+        );
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "group 'no-such-group' does not exist"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn wrap_command() {
+        let account = Account {
+            uid: 1001,
+            gid: 1002,
+            groups: Some(vec![1002, 27]),
+            user: Some(("svc".to_string(), "/var/lib/svc".to_string())),
+        };
+        assert_eq!(
+            account.wrap(&["id".to_string()]),
+            [
+                "setpriv",
+                "--reuid=1001",
+                "--regid=1002",
+                "--groups=1002,27",
+                "--",
+                "env",
+                "HOME=/var/lib/svc",
+                "USER=svc",
+                "LOGNAME=svc",
+                "id"
+            ]
+        );
+        let account = Account {
+            groups: None,
+            user: None,
+            ..account
+        };
+        assert_eq!(
+            account.wrap(&["id".to_string()]),
+            [
+                "setpriv",
+                "--reuid=1001",
+                "--regid=1002",
+                "--keep-groups",
+                "--",
+                "id"
+            ]
+        );
+    }
+}
@@ -0,0 +1,614 @@
+use crate::config::{Cgroup, CgroupMode, Signal};
+use gtk::gio::{self, BusType, Cancellable, DBusCallFlags, DBusConnection};
+use gtk::glib::variant::ToVariant;
+use gtk::glib::{Variant, VariantTy};
+use log::{debug, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::{fs, io};
+
+/// Well-known name of the systemd manager, on the session bus it is the user one
+const SYSTEMD_NAME: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+
+/// Timeout of a single call to the systemd manager, in milliseconds
+const CALL_TIMEOUT: i32 = 5000;
+
+/// How long systemd may take to move the program into the new scope
+const MOVE_TIMEOUT: Duration = Duration::from_secs(5);
+const MOVE_CHECK_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Period of the CPU quota of the direct group, in microseconds
+const CPU_PERIOD: u64 = 100_000;
+
+/// Number of the groups created by the tray, keeping their names unique
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Control group of the program, holding it with all its descendants,
+/// including the ones which left its process group or session.
+///
+pub struct ControlGroup {
+    /// Directory of the group in the cgroup v2 hierarchy
+    dir: PathBuf,
+    /// Transient systemd scope owning the group, if any
+    scope: Option<(DBusConnection, String)>,
+}
+
+/// Control group being prepared for the program to be spawned into it
+///
+pub enum Placement {
+    /// The scope is created once the program is forked, before its exec
+    Scope(JoinHandle<io::Result<ControlGroup>>),
+    /// The group is created already, the program moves itself into it
+    Subtree(ControlGroup),
+}
+
+impl Placement {
+    /// Prepare the group and setup the command to move the program into it before the exec,
+    /// so the program and everything it starts are in the group from the beginning.
+    /// The spawn fails if the program can't be moved.
+    ///
+    pub fn prepare(config: &Cgroup, id: &str, command: &mut Command) -> io::Result<Self> {
+        let name = group_name(id);
+        match config.get_mode() {
+            CgroupMode::Systemd => {
+                let (connection, service) = connect_manager()?;
+                let unit = format!("{}.scope", name);
+                Self::prepare_scope(connection, service, config, unit, command)
+            }
+            CgroupMode::Direct => {
+                let parent = config.get_parent().expect("direct mode without parent");
+                Self::prepare_subtree(config, &parent.join(name), command)
+            }
+        }
+    }
+
+    /// The forked program reports its pid and waits till the helper thread creates the scope
+    /// with it and systemd moves it there.
+    ///
+    fn prepare_scope(
+        connection: DBusConnection,
+        service: Option<&'static str>,
+        config: &Cgroup,
+        unit: String,
+        command: &mut Command,
+    ) -> io::Result<Self> {
+        let (pid_read, pid_write) = open_pipe()?;
+        let (release_read, release_write) = open_pipe()?;
+        let config = config.clone();
+        let helper = thread::spawn(move || {
+            let mut pid = [0u8; 4];
+            File::from(pid_read).read_exact(&mut pid).map_err(|e| {
+                io::Error::new(e.kind(), "the program exited before the scope was created")
+            })?;
+            let pid = u32::from_ne_bytes(pid);
+            let result = start_scope(&connection, service, &unit, pid, &config)
+                .and_then(|()| await_moved(pid, &unit));
+            // the program proceeds to the exec only after the successful move
+            let _ = File::from(release_write).write_all(&[result.is_ok() as u8]);
+            Ok(ControlGroup {
+                dir: result?,
+                scope: Some((connection, unit)),
+            })
+        });
+        unsafe {
+            command.pre_exec(move || {
+                let pid = libc::getpid().to_ne_bytes();
+                if libc::write(pid_write.as_raw_fd(), pid.as_ptr().cast(), pid.len()) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut moved = 0u8;
+                match libc::read(release_read.as_raw_fd(), (&mut moved as *mut u8).cast(), 1) {
+                    1 if moved == 1 => Ok(()),
+                    _ => Err(io::Error::from_raw_os_error(libc::ECANCELED)),
+                }
+            });
+        }
+        Ok(Placement::Scope(helper))
+    }
+
+    /// The forked program writes itself into `cgroup.procs`, opened before the fork.
+    ///
+    fn prepare_subtree(config: &Cgroup, dir: &Path, command: &mut Command) -> io::Result<Self> {
+        fs::create_dir(dir).map_err(|e| {
+            let msg = format!("failed to create cgroup {}: {}", dir.display(), e);
+            io::Error::new(e.kind(), msg)
+        })?;
+        // removed on failure
+        let group = ControlGroup {
+            dir: dir.to_path_buf(),
+            scope: None,
+        };
+        enable_controllers(dir, config);
+        if let Some(memory_max) = config.get_memory_max() {
+            group.write("memory.max", &memory_max.to_string())?;
+        }
+        if let Some(quota) = config.get_cpu_quota() {
+            let quota = quota as u64 * CPU_PERIOD / 100;
+            group.write("cpu.max", &format!("{} {}", quota, CPU_PERIOD))?;
+        }
+        let procs = OpenOptions::new()
+            .write(true)
+            .open(dir.join("cgroup.procs"))?;
+        unsafe {
+            command.pre_exec(move || {
+                match libc::write(procs.as_raw_fd(), b"0".as_ptr().cast(), 1) {
+                    -1 => Err(io::Error::last_os_error()),
+                    _ => Ok(()),
+                }
+            });
+        }
+        Ok(Placement::Subtree(group))
+    }
+
+    /// Take the group of the spawned program. Call it once the command is dropped,
+    /// so the scope helper sees the failed spawn.
+    ///
+    pub fn finish(self) -> io::Result<ControlGroup> {
+        match self {
+            Placement::Scope(helper) => helper
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("the scope helper panicked"))),
+            Placement::Subtree(group) => Ok(group),
+        }
+    }
+}
+
+impl ControlGroup {
+    pub fn get_dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Send the signal to every process of the group.
+    ///
+    pub fn kill(&self, signal: Signal) -> io::Result<()> {
+        if !self.is_populated() {
+            return Ok(()); // all exited, the scope may be collected already
+        }
+        if let Some((connection, unit)) = self.scope.as_ref() {
+            return kill_unit(connection, Some(SYSTEMD_NAME), unit, signal);
+        }
+        if signal == Signal::KILL && self.dir.join("cgroup.kill").exists() {
+            return self.write("cgroup.kill", "1");
+        }
+        for pid in self.list_processes()? {
+            if unsafe { libc::kill(pid as libc::pid_t, signal.get_number()) } != 0 {
+                match io::Error::last_os_error() {
+                    e if e.raw_os_error() == Some(libc::ESRCH) => {} // already exited
+                    e => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if any process of the group is still running.
+    ///
+    pub fn is_populated(&self) -> bool {
+        self.list_processes()
+            .is_ok_and(|processes| !processes.is_empty())
+    }
+
+    fn list_processes(&self) -> io::Result<Vec<u32>> {
+        let procs = match fs::read_to_string(self.dir.join("cgroup.procs")) {
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            procs => procs?,
+        };
+        Ok(procs
+            .lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .collect())
+    }
+
+    fn write(&self, file: &str, value: &str) -> io::Result<()> {
+        fs::write(self.dir.join(file), value).map_err(|e| {
+            let msg = format!(
+                "failed to set {} of cgroup {}: {}",
+                file,
+                self.dir.display(),
+                e
+            );
+            io::Error::new(e.kind(), msg)
+        })
+    }
+}
+
+impl Drop for ControlGroup {
+    /// Remove the direct group, the systemd one is collected by systemd.
+    ///
+    fn drop(&mut self) {
+        if self.scope.is_none() {
+            if let Err(e) = fs::remove_dir(&self.dir) {
+                warn!("Failed to remove cgroup {}: {}", self.dir.display(), e);
+            }
+        }
+    }
+}
+
+/// Unique name of the group, also valid as the systemd unit name.
+///
+fn group_name(id: &str) -> String {
+    let id: String = id
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '_' {
+            true => c,
+            false => '-',
+        })
+        .collect();
+    let count = CREATED.fetch_add(1, Ordering::Relaxed);
+    format!("program-tray-{}-{}-{}", id, std::process::id(), count)
+}
+
+/// Let the group use the controllers of its limits. The parent may have them enabled already
+/// or be unable to, then setting the limit reports the missing controller.
+///
+fn enable_controllers(dir: &Path, config: &Cgroup) {
+    let Some(parent) = dir.parent() else {
+        return;
+    };
+    let controllers = [
+        ("+memory", config.get_memory_max().is_some()),
+        ("+cpu", config.get_cpu_quota().is_some()),
+    ];
+    for (controller, _) in controllers.iter().filter(|(_, needed)| *needed) {
+        if let Err(e) = fs::write(parent.join("cgroup.subtree_control"), controller) {
+            debug!(
+                "Failed to enable {} in {}: {}",
+                controller,
+                parent.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Pipe closed on exec, so only the forked program and the tray use it.
+///
+fn open_pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// Mount point of the cgroup v2 hierarchy, which is `/sys/fs/cgroup` unless it is hybrid.
+///
+pub(crate) fn find_root() -> io::Result<PathBuf> {
+    fs::read_to_string("/proc/self/mounts")?
+        .lines()
+        .map(|line| line.split(' ').collect::<Vec<_>>())
+        .find(|fields| fields.len() > 2 && fields[2] == "cgroup2")
+        .map(|fields| PathBuf::from(fields[1]))
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "cgroup v2 is not mounted"))
+}
+
+/// Wait till systemd moves the process into the scope and return the scope directory.
+///
+fn await_moved(pid: u32, unit: &str) -> io::Result<PathBuf> {
+    let deadline = Instant::now() + MOVE_TIMEOUT;
+    let suffix = format!("/{}", unit);
+    loop {
+        let cgroup = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+        // the cgroup v2 entry has no controllers: `0::/path`
+        let path = cgroup.lines().find_map(|line| line.strip_prefix("0::"));
+        if let Some(path) = path.filter(|path| path.ends_with(&suffix)) {
+            return Ok(find_root()?.join(path.trim_start_matches('/')));
+        }
+        if Instant::now() >= deadline {
+            let msg = format!("the program was not moved into {}", unit);
+            return Err(io::Error::new(ErrorKind::TimedOut, msg));
+        }
+        thread::sleep(MOVE_CHECK_INTERVAL);
+    }
+}
+
+/// Properties of the transient scope: the process and the limits.
+/// The failed scope is collected as well, so its name is never left taken.
+///
+fn scope_properties(pid: u32, config: &Cgroup) -> Vec<(&'static str, Variant)> {
+    let mut properties = vec![
+        ("Description", "program-tray program".to_variant()),
+        ("PIDs", vec![pid].to_variant()),
+        ("CollectMode", "inactive-or-failed".to_variant()),
+    ];
+    if let Some(memory_max) = config.get_memory_max() {
+        properties.push(("MemoryMax", memory_max.to_variant()));
+    }
+    if let Some(quota) = config.get_cpu_quota() {
+        // microseconds of CPU time per second
+        properties.push(("CPUQuotaPerSecUSec", (quota as u64 * 10_000).to_variant()));
+    }
+    properties
+}
+
+fn call(
+    connection: &DBusConnection,
+    service: Option<&str>,
+    method: &str,
+    parameters: Variant,
+    reply: &str,
+) -> io::Result<Variant> {
+    connection
+        .call_sync(
+            service,
+            SYSTEMD_PATH,
+            MANAGER_INTERFACE,
+            method,
+            Some(&parameters),
+            Some(VariantTy::new(reply).unwrap()),
+            DBusCallFlags::NONE,
+            CALL_TIMEOUT,
+            Cancellable::NONE,
+        )
+        .map_err(io::Error::other)
+}
+
+/// Connection to the systemd manager of the user, on the session bus.
+///
+fn connect_manager() -> io::Result<(DBusConnection, Option<&'static str>)> {
+    #[cfg(test)]
+    if let Some(connection) = tests::MANAGER.with(|manager| manager.borrow().clone()) {
+        return Ok((connection, None));
+    }
+    let connection =
+        gio::bus_get_sync(BusType::Session, Cancellable::NONE).map_err(io::Error::other)?;
+    Ok((connection, Some(SYSTEMD_NAME)))
+}
+
+/// Create the transient scope of the process with the limits.
+///
+fn start_scope(
+    connection: &DBusConnection,
+    service: Option<&str>,
+    unit: &str,
+    pid: u32,
+    config: &Cgroup,
+) -> io::Result<()> {
+    let auxiliary: Vec<(&str, Vec<(&str, Variant)>)> = Vec::new();
+    let parameters = (unit, "fail", scope_properties(pid, config), auxiliary).to_variant();
+    let reply = call(connection, service, "StartTransientUnit", parameters, "(o)")?;
+    debug!("Started {} as job {}", unit, reply.child_value(0));
+    Ok(())
+}
+
+/// Send the signal to every process of the unit.
+///
+fn kill_unit(
+    connection: &DBusConnection,
+    service: Option<&str>,
+    unit: &str,
+    signal: Signal,
+) -> io::Result<()> {
+    let parameters = (unit, "all", signal.get_number()).to_variant();
+    call(connection, service, "KillUnit", parameters, "()").map(|_| ())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::parse_content;
+    use gtk::gio::prelude::*;
+    use gtk::gio::{
+        DBusCapabilityFlags, DBusConnectionFlags, DBusMessage, DBusMessageType,
+        DBusSendMessageFlags,
+    };
+    use gtk::glib::variant::ObjectPath;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader};
+    use std::os::unix::net::UnixStream;
+    use std::process::Stdio;
+    use std::sync::{Arc, Mutex};
+
+    const JOB: &str = "/org/freedesktop/systemd1/job/1";
+
+    /// How long the mock manager takes to answer for the units of the `slow` program
+    pub(crate) const SLOW_REPLY: Duration = Duration::from_secs(1);
+
+    thread_local! {
+        /// Manager used in place of the systemd one by the placements prepared in the thread
+        pub(super) static MANAGER: RefCell<Option<DBusConnection>> = const { RefCell::new(None) };
+    }
+
+    /// Make the placements prepared in the current thread use the mock manager.
+    /// Returns the manager connection, which must be kept.
+    ///
+    pub(crate) fn use_mock_manager() -> DBusConnection {
+        let (client, server) = mock_manager(Arc::new(Mutex::new(Vec::new())));
+        MANAGER.with(|manager| *manager.borrow_mut() = Some(client));
+        server
+    }
+
+    fn connect(stream: UnixStream, flags: DBusConnectionFlags) -> DBusConnection {
+        let socket = unsafe { gio::Socket::from_fd(stream) }.unwrap();
+        let stream = socket.connection_factory_create_connection();
+        let guid = flags
+            .contains(DBusConnectionFlags::AUTHENTICATION_SERVER)
+            .then(gio::dbus_generate_guid);
+        DBusConnection::new_sync(&stream, guid.as_deref(), flags, None, Cancellable::NONE).unwrap()
+    }
+
+    /// Serve the mock systemd manager over a private connection, recording the calls.
+    /// Returns the client connection and the manager one, which must be kept.
+    ///
+    fn mock_manager(calls: Arc<Mutex<Vec<(String, Variant)>>>) -> (DBusConnection, DBusConnection) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let connection = connect(server, DBusConnectionFlags::AUTHENTICATION_SERVER);
+            connection.add_filter(move |connection, message, incoming| {
+                if !incoming || message.message_type() != DBusMessageType::MethodCall {
+                    return Some(message.clone());
+                }
+                let delay = match unit_of(message).is_some_and(|unit| unit.contains("-slow-")) {
+                    true => SLOW_REPLY,
+                    false => Duration::ZERO,
+                };
+                let reply = handle_call(message, &calls);
+                // answered in background, since the filter blocks every connection
+                let reply = reply.to_blob(DBusCapabilityFlags::NONE).unwrap();
+                let connection = connection.clone();
+                thread::spawn(move || {
+                    thread::sleep(delay);
+                    let reply = DBusMessage::from_blob(&reply, DBusCapabilityFlags::NONE).unwrap();
+                    let _ = connection.send_message(&reply, DBusSendMessageFlags::NONE);
+                });
+                None
+            });
+            connection
+        });
+        let client = connect(client, DBusConnectionFlags::AUTHENTICATION_CLIENT);
+        (client, server.join().unwrap())
+    }
+
+    fn unit_of(message: &DBusMessage) -> Option<String> {
+        message.body()?.child_value(0).str().map(String::from)
+    }
+
+    fn handle_call(message: &DBusMessage, calls: &Mutex<Vec<(String, Variant)>>) -> DBusMessage {
+        let method = message.member().unwrap().to_string();
+        let body = message.body().unwrap();
+        calls.lock().unwrap().push((method.clone(), body.clone()));
+        let reply = message.new_method_reply();
+        let unit = unit_of(message).unwrap_or_default();
+        match method.as_str() {
+            "StartTransientUnit" if unit == "taken.scope" || unit.contains("-slow-") => {
+                return message.new_method_error_literal(
+                    "org.freedesktop.systemd1.UnitExists",
+                    &format!("Unit {} was already loaded or has a fragment file.", unit),
+                );
+            }
+            "StartTransientUnit" => {
+                reply.set_body(&(ObjectPath::try_from(JOB).unwrap(),).to_variant())
+            }
+            "KillUnit" if body.child_value(0).str() == Some("missing.scope") => {
+                return message.new_method_error_literal(
+                    "org.freedesktop.systemd1.NoSuchUnit",
+                    "Unit missing.scope not loaded.",
+                );
+            }
+            _ => {}
+        }
+        reply
+    }
+
+    #[test]
+    fn call_systemd_manager() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (connection, _manager) = mock_manager(Arc::clone(&calls));
+        let program = parse_content(
+            "id = 'id1'\ncommand = 'command1'\n[cgroup]\nmemory_max = 1048576\ncpu_quota = 50",
+        )
+        .unwrap();
+        let config = program.get_cgroup().unwrap();
+
+        start_scope(&connection, None, "test.scope", 42, config).unwrap();
+        kill_unit(&connection, None, "test.scope", Signal::TERM).unwrap();
+        let res = kill_unit(&connection, None, "missing.scope", Signal::KILL);
+        assert!(res.err().unwrap().to_string().contains("not loaded"));
+
+        let calls = calls.lock().unwrap();
+        let (method, body) = &calls[0];
+        assert_eq!(method, "StartTransientUnit");
+        assert_eq!(body.type_().as_str(), "(ssa(sv)a(sa(sv)))");
+        assert_eq!(body.child_value(0).str(), Some("test.scope"));
+        assert_eq!(body.child_value(1).str(), Some("fail"));
+        let properties: HashMap<String, Variant> = body
+            .child_value(2)
+            .iter()
+            .map(|property| {
+                let (name, value) = property.get::<(String, Variant)>().unwrap();
+                (name, value)
+            })
+            .collect();
+        assert_eq!(properties["PIDs"].get::<Vec<u32>>(), Some(vec![42]));
+        assert_eq!(properties["MemoryMax"].get::<u64>(), Some(1048576));
+        assert_eq!(properties["CPUQuotaPerSecUSec"].get::<u64>(), Some(500_000));
+        assert_eq!(properties["CollectMode"].str(), Some("inactive-or-failed"));
+
+        let (method, body) = &calls[1];
+        assert_eq!(method, "KillUnit");
+        assert_eq!(
+            body.get::<(String, String, i32)>(),
+            Some(("test.scope".to_string(), "all".to_string(), libc::SIGTERM))
+        );
+    }
+
+    #[test]
+    fn scope_placement_failure() -> io::Result<()> {
+        // the program must not be executed outside of the refused scope
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let (connection, _manager) = mock_manager(Arc::clone(&calls));
+        let program = parse_content("id = 'id1'\ncommand = 'command1'\n[cgroup]")?;
+
+        let dir = tempfile::tempdir()?;
+        let marker = dir.path().join("executed");
+        let mut command = Command::new("touch");
+        command.arg(&marker);
+        let config = program.get_cgroup().unwrap();
+        let unit = "taken.scope".to_string();
+        let placement = Placement::prepare_scope(connection, None, config, unit, &mut command)?;
+        let spawned = command.spawn();
+        drop(command);
+        let res = placement.finish();
+        assert!(res.err().unwrap().to_string().contains("already loaded"));
+        assert!(spawned.is_err());
+        assert!(!marker.exists());
+        assert_eq!(calls.lock().unwrap()[0].0, "StartTransientUnit");
+        Ok(())
+    }
+
+    #[test]
+    fn direct_placement() -> io::Result<()> {
+        // requires a writable cgroup v2 hierarchy, like a delegated one or as root
+        let Ok(parent) = find_root().map(|root| root.join(group_name("test"))) else {
+            return Ok(());
+        };
+        if fs::create_dir(&parent).is_err() {
+            return Ok(());
+        }
+        let program = parse_content(&format!(
+            "id = 'id1'\ncommand = 'command1'\n[cgroup]\nmode = 'direct'\nparent = '{}'",
+            parent.display()
+        ))?;
+
+        let mut command = Command::new("sh");
+        command
+            .args([
+                "-c",
+                "setsid sleep 60 >/dev/null & grep ^0:: /proc/self/cgroup; wait",
+            ])
+            .stdout(Stdio::piped());
+        let placement = Placement::prepare(program.get_cgroup().unwrap(), "id1", &mut command)?;
+        let mut child = command.spawn()?;
+        drop(command);
+        let group = placement.finish()?;
+
+        let mut output = String::new();
+        BufReader::new(child.stdout.take().unwrap()).read_line(&mut output)?;
+        let dir = group.get_dir().to_path_buf();
+        assert!(output
+            .trim_end()
+            .ends_with(dir.file_name().unwrap().to_str().unwrap()));
+        assert!(group.is_populated());
+
+        group.kill(Signal::KILL)?;
+        child.wait()?;
+        let deadline = Instant::now() + MOVE_TIMEOUT;
+        while group.is_populated() && Instant::now() < deadline {
+            thread::sleep(MOVE_CHECK_INTERVAL);
+        }
+        assert!(!group.is_populated());
+        drop(group);
+        assert!(!dir.exists());
+        fs::remove_dir(parent)
+    }
+}
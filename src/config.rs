@@ -1,196 +1,2359 @@
+use crate::account::Account;
+use crate::dotenv;
+use crate::template::{replace_args, replace_known_args, split_command, Quoting};
 use regex::Regex;
+use serde::de::DeserializeOwned;
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
 use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 use std::{fs, io};
 use toml;
 
+/// Shell of `shell = true`
+const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// The structure of TOML-config file with several `[[program]]` entries.
+///
+#[derive(Debug, Deserialize)]
+struct Programs {
+    program: Vec<Program>,
+}
+
 /// The structure of TOML-config file.
 ///
 #[derive(Debug, Deserialize)]
 pub struct Program {
     id: String,
-    command: String,
+    command: CommandLine,
+    /// Run the command line with the shell: `true` for the default one or its path
+    shell: Option<Shell>,
     #[serde(default)]
     superuser: bool,
+    /// Command giving the superuser rights, pkexec by default
+    escalation: Option<Escalation>,
+    /// Account to run the program as, by name or id
+    user: Option<String>,
+    /// The primary group of `user` by default
+    group: Option<String>,
+    /// Supplementary groups, the ones of `user` by default
+    #[serde(default)]
+    groups: Vec<String>,
+    /// The account resolved from `user`, `group` and `groups` while validating
+    #[serde(skip)]
+    account: Option<Account>,
+    /// Run the program on a pseudo-terminal instead of pipes
+    #[serde(default)]
+    pty: bool,
     input: Option<String>,
+    #[serde(default = "default_max_line_length")]
+    max_line_length: usize,
+    /// Regex matching the output line which tells the program is ready
+    ready_pattern: Option<String>,
+    /// Time to wait for the ready line before the start is failed, in seconds
+    ready_timeout: Option<f64>,
+    /// Ids of the programs to be started and ready before this one
+    #[serde(default)]
+    depends_on: Vec<String>,
     #[serde(default)]
-    args: HashMap<String, String>,
+    pre_start: Vec<String>,
+    #[serde(default)]
+    post_start: Vec<String>,
+    #[serde(default)]
+    pre_stop: Vec<String>,
+    #[serde(default)]
+    post_stop: Vec<String>,
+    #[serde(default)]
+    args: HashMap<String, Arg>,
     #[serde(default)]
     env: HashMap<String, String>,
+    /// Dotenv files with the variables overridden by `env`, relative to the config file
+    #[serde(default)]
+    env_file: Vec<String>,
+    /// Directory to run the program in, the current one by default
+    working_dir: Option<String>,
+    /// File mode creation mask in octal, e.g. "027"
+    umask: Option<String>,
+    /// Start the program with `env` only, not inheriting the environment of the tray
+    #[serde(default)]
+    clear_env: bool,
+    /// Variables inherited in spite of `clear_env`, `PREFIX_*` matches by prefix
+    #[serde(default)]
+    env_passthrough: Vec<String>,
+    #[serde(default)]
+    restart: Restart,
+    #[serde(default)]
+    stop: Stop,
+    #[serde(default)]
+    limits: Limits,
+    cgroup: Option<Cgroup>,
+    log: Option<Log>,
+    health: Option<Health>,
     #[serde(default)]
     ui: UI,
 }
 
-#[derive(Default, Debug, Deserialize)]
-struct UI {
-    title: Option<String>,
-    #[serde(default)]
-    icons: Icons,
+fn default_max_line_length() -> usize {
+    4096
 }
 
-#[derive(Debug, Default, Deserialize)]
+/// Commands run around the start and the stop of the program.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// Before the start, the failed command aborts it
+    PreStart,
+    /// After the start
+    PostStart,
+    /// Before the stop signal
+    PreStop,
+    /// After the exit
+    PostStop,
+}
+
+impl Hook {
+    pub const ALL: [Hook; 4] = [
+        Hook::PreStart,
+        Hook::PostStart,
+        Hook::PreStop,
+        Hook::PostStop,
+    ];
+}
+
+impl fmt::Display for Hook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Hook::PreStart => "pre_start",
+            Hook::PostStart => "post_start",
+            Hook::PreStop => "pre_stop",
+            Hook::PostStop => "post_stop",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// When the exited program should be started again.
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// The `[restart]` table: automatic restart of the exited program.
+///
+#[derive(Debug, Clone, Deserialize)]
 #[serde(default)]
-struct Icons {
-    on: Option<String>,
-    off: Option<String>,
+pub struct Restart {
+    policy: RestartPolicy,
+    max_retries: u32,
+    /// Delay before the first retry, in seconds
+    backoff_base: f64,
+    /// Upper bound of the exponentially growing delay, in seconds
+    backoff_max: f64,
 }
 
-impl Program {
-    pub fn get_id(&self) -> &str {
-        &self.id
+impl Default for Restart {
+    fn default() -> Self {
+        Restart {
+            policy: RestartPolicy::Never,
+            max_retries: 3,
+            backoff_base: 1.0,
+            backoff_max: 30.0,
+        }
     }
+}
 
-    pub fn get_env(&self) -> &HashMap<String, String> {
-        &self.env
+impl Restart {
+    pub fn get_policy(&self) -> RestartPolicy {
+        self.policy
     }
 
-    pub fn get_command(&self) -> String {
-        replace_args(&self.command, &self.args)
+    pub fn get_max_retries(&self) -> u32 {
+        self.max_retries
     }
 
-    pub fn need_superuser(&self) -> bool {
-        self.superuser
+    /// Check if the program exited with the given result should be started again.
+    ///
+    pub fn is_required(&self, success: bool) -> bool {
+        match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure => !success,
+            RestartPolicy::Always => true,
+        }
     }
 
-    pub fn get_input(&self) -> Option<String> {
-        Some(replace_args(self.input.as_ref()?, &self.args))
+    /// Delay before the restart attempt (counting from 1).
+    ///
+    pub fn get_backoff(&self, attempt: u32) -> Duration {
+        let factor = 2f64.powi(attempt.saturating_sub(1).min(63) as i32);
+        Duration::from_secs_f64((self.backoff_base * factor).min(self.backoff_max))
     }
 
-    pub fn get_title(&self) -> &str {
-        self.ui
-            .title
-            .as_deref()
-            .map_or(self.id.as_str(), |title| title)
+    fn validate(&self) -> io::Result<()> {
+        validate_seconds("restart.backoff_base", self.backoff_base)?;
+        validate_seconds("restart.backoff_max", self.backoff_max)
     }
+}
 
-    pub fn get_icon_on_path(&self) -> Option<&str> {
-        self.ui.icons.on.as_deref()
+/// The `[stop]` table: how the running program is asked to quit.
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Stop {
+    signal: Signal,
+    /// Grace period before escalating to a stronger signal, in seconds
+    timeout: f64,
+    /// Also signal descendants which left the process group of the program
+    kill_descendants: bool,
+}
+
+impl Default for Stop {
+    fn default() -> Self {
+        Stop {
+            signal: Signal::INT,
+            timeout: 10.0,
+            kill_descendants: false,
+        }
     }
+}
 
-    pub fn get_icon_off_path(&self) -> Option<&str> {
-        self.ui.icons.off.as_deref()
+impl Stop {
+    pub fn get_signal(&self) -> Signal {
+        self.signal
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout)
+    }
+
+    pub fn need_kill_descendants(&self) -> bool {
+        self.kill_descendants
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        validate_seconds("stop.timeout", self.timeout)
     }
 }
 
-fn replace_args(str: &String, args: &HashMap<String, String>) -> String {
-    // Create a regex to match placeholders like $arg
-    let re = Regex::new(r"\$(\w+)").expect("Failed to compile regex");
+/// The `[limits]` table: resource limits and priorities of the program,
+/// the inherited ones are kept if not set.
+///
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Max number of open files
+    open_files: Option<u64>,
+    /// Max size of the virtual memory, in bytes
+    address_space: Option<u64>,
+    /// Max CPU time, in seconds
+    cpu_time: Option<u64>,
+    /// Max size of a core dump in bytes, 0 disables them
+    core_size: Option<u64>,
+    /// Scheduling priority from -20 (highest) to 19 (lowest)
+    nice: Option<i32>,
+    /// I/O scheduling class, best-effort by default
+    io_class: Option<IoClass>,
+    /// I/O priority within the class from 0 (highest) to 7 (lowest)
+    io_priority: Option<u8>,
+}
+
+/// I/O scheduling class, as of `ionice`
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    /// Only served when nothing else uses the disk, without priorities
+    Idle,
+}
+
+/// Priority within the class if only the class is set
+const DEFAULT_IO_PRIORITY: u8 = 4;
+
+impl Limits {
+    pub fn get_open_files(&self) -> Option<u64> {
+        self.open_files
+    }
+
+    pub fn get_address_space(&self) -> Option<u64> {
+        self.address_space
+    }
+
+    pub fn get_cpu_time(&self) -> Option<u64> {
+        self.cpu_time
+    }
+
+    pub fn get_core_size(&self) -> Option<u64> {
+        self.core_size
+    }
+
+    pub fn get_nice(&self) -> Option<i32> {
+        self.nice
+    }
 
-    let result = re.replace_all(&str, |caps: &regex::Captures| {
-        // Extract the argument name (e.g., "arg" from "$arg")
-        let arg_name = String::from_str(caps.get(1).unwrap().as_str()).unwrap();
+    /// I/O scheduling class and priority, if any of them is set.
+    ///
+    pub fn get_io_priority(&self) -> Option<(IoClass, u8)> {
+        match (self.io_class, self.io_priority) {
+            (None, None) => None,
+            (Some(IoClass::Idle), _) => Some((IoClass::Idle, 0)),
+            (class, priority) => Some((
+                class.unwrap_or(IoClass::BestEffort),
+                priority.unwrap_or(DEFAULT_IO_PRIORITY),
+            )),
+        }
+    }
 
-        // Look up the argument in the map and return its value or leave it unchanged if not found
-        args.get(&arg_name)
-            .cloned()
-            .unwrap_or_else(|| format!("${}", arg_name))
-    });
+    pub fn is_empty(&self) -> bool {
+        self.to_string().is_empty()
+    }
 
-    result.to_string()
+    fn validate(&self) -> io::Result<()> {
+        let msg = match (self.nice, self.io_class, self.io_priority) {
+            (Some(nice), _, _) if !(-20..=19).contains(&nice) => {
+                "limits.nice must be from -20 to 19"
+            }
+            (_, _, Some(priority)) if priority > 7 => "limits.io_priority must be from 0 to 7",
+            (_, Some(IoClass::Idle), Some(_)) => "limits.io_priority is not used by the idle class",
+            _ => return Ok(()),
+        };
+        Err(io::Error::new(ErrorKind::InvalidInput, msg))
+    }
 }
 
-pub fn parse_properties_file(file_path: &str) -> io::Result<Program> {
-    let content = fs::read_to_string(file_path)?;
-    parse_content(&content)
+impl fmt::Display for Limits {
+    /// The limits which are set, separated by commas
+    ///
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut limits = Vec::new();
+        if let Some(open_files) = self.open_files {
+            limits.push(format!("open files {}", open_files));
+        }
+        if let Some(address_space) = self.address_space {
+            limits.push(format!("address space {} bytes", address_space));
+        }
+        if let Some(cpu_time) = self.cpu_time {
+            limits.push(format!("CPU time {} s", cpu_time));
+        }
+        if let Some(core_size) = self.core_size {
+            limits.push(format!("core size {} bytes", core_size));
+        }
+        if let Some(nice) = self.nice {
+            limits.push(format!("nice {}", nice));
+        }
+        match self.get_io_priority() {
+            Some((IoClass::Idle, _)) => limits.push("I/O idle".to_string()),
+            Some((IoClass::BestEffort, priority)) => {
+                limits.push(format!("I/O best-effort {}", priority))
+            }
+            Some((IoClass::Realtime, priority)) => {
+                limits.push(format!("I/O realtime {}", priority))
+            }
+            None => {}
+        }
+        write!(f, "{}", limits.join(", "))
+    }
+}
+
+/// The `[cgroup]` table: the control group holding the program with all its descendants,
+/// so they are accounted, limited and stopped together.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct Cgroup {
+    #[serde(default)]
+    mode: CgroupMode,
+    /// Delegated cgroup v2 directory to create the group in, for the direct mode
+    parent: Option<String>,
+    /// Max memory of all processes, in bytes
+    memory_max: Option<u64>,
+    /// Max CPU time of all processes, in percent of a single CPU
+    cpu_quota: Option<u32>,
+}
+
+/// How the control group is created
+///
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CgroupMode {
+    /// Transient scope of the systemd user manager, created over D-Bus
+    #[default]
+    Systemd,
+    /// Subdirectory of the delegated `parent` in the cgroup v2 hierarchy
+    Direct,
 }
 
-fn parse_content(content: &str) -> io::Result<Program> {
-    match toml::from_str(&content) {
-        Ok(program) => Ok(program),
-        Err(error) => Err(io::Error::new(ErrorKind::InvalidInput, error.message())),
+impl Cgroup {
+    pub fn get_mode(&self) -> CgroupMode {
+        self.mode
+    }
+
+    pub fn get_parent(&self) -> Option<PathBuf> {
+        self.parent.as_deref().map(expand_home)
+    }
+
+    pub fn get_memory_max(&self) -> Option<u64> {
+        self.memory_max
+    }
+
+    pub fn get_cpu_quota(&self) -> Option<u32> {
+        self.cpu_quota
+    }
+
+    fn validate(&self) -> io::Result<()> {
+        let msg = match (self.mode, self.get_parent()) {
+            (CgroupMode::Direct, None) => "cgroup.parent is required by the direct mode",
+            (CgroupMode::Direct, Some(parent)) if !parent.is_absolute() => {
+                "cgroup.parent must be an absolute path"
+            }
+            (CgroupMode::Systemd, Some(_)) => "cgroup.parent is only used by the direct mode",
+            _ if self.cpu_quota == Some(0) => "cgroup.cpu_quota must be positive",
+            _ => return Ok(()),
+        };
+        Err(io::Error::new(ErrorKind::InvalidInput, msg))
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+/// POSIX signal, configured by name (`"TERM"`, `"SIGTERM"`) or by number.
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "SignalValue")]
+pub struct Signal(i32);
 
-    #[test]
-    fn read_not_exists() {
-        let res = parse_properties_file("invalid");
-        assert!(res.is_err());
-        assert_eq!(res.err().unwrap().kind(), ErrorKind::NotFound);
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SignalValue {
+    Name(String),
+    Number(i32),
+}
+
+const SIGNAL_NAMES: [(&str, i32); 10] = [
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("ALRM", libc::SIGALRM),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("WINCH", libc::SIGWINCH),
+];
+
+impl Signal {
+    pub const INT: Signal = Signal(libc::SIGINT);
+    pub const TERM: Signal = Signal(libc::SIGTERM);
+    pub const KILL: Signal = Signal(libc::SIGKILL);
+
+    pub fn get_number(&self) -> i32 {
+        self.0
     }
 
-    #[test]
-    fn read_invalid_config() -> io::Result<()> {
-        let temp_file = NamedTempFile::new()?;
-        let path = temp_file.path().to_str().unwrap();
-        // Write some data to the file
-        temp_file.as_file().write_all(br#"garbage"#)?;
+    fn get_name(&self) -> Option<&'static str> {
+        SIGNAL_NAMES
+            .iter()
+            .find(|(_, number)| *number == self.0)
+            .map(|(name, _)| *name)
+    }
+}
 
-        let res = parse_properties_file(path);
-        assert!(res.is_err());
-        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
-        Ok(())
+impl TryFrom<SignalValue> for Signal {
+    type Error = String;
+
+    fn try_from(value: SignalValue) -> Result<Self, Self::Error> {
+        match value {
+            SignalValue::Name(name) => Signal::from_str(&name),
+            SignalValue::Number(number) if (1..=64).contains(&number) => Ok(Signal(number)),
+            SignalValue::Number(number) => Err(format!("invalid signal number {}", number)),
+        }
     }
+}
 
-    #[test]
-    fn read_config() -> io::Result<()> {
-        let temp_file = NamedTempFile::new()?;
-        let path = temp_file.path().to_str().unwrap();
-        // Write some data to the file
-        temp_file.as_file().write_all(
-            br#"
-          id = "id1"
-          command = "command1 $arg1"
-          superuser = true
-          input = "arg2"
-          
-          [args]
-          arg1 = "arg2"
+impl FromStr for Signal {
+    type Err = String;
 
-          [env]
-          ENVVAR = "env1"
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim().to_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        SIGNAL_NAMES
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, number)| Signal(*number))
+            .ok_or_else(|| format!("unknown signal '{}'", s))
+    }
+}
 
-          [ui]
-          title = "title1"
-          
-          [ui.icons]
-          on = "/some/path/to/file"
-          off = "/some/path/to/file"
-        "#,
-        )?;
+impl fmt::Display for Signal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.get_name() {
+            Some(name) => write!(f, "SIG{}", name),
+            None => write!(f, "signal {}", self.0),
+        }
+    }
+}
 
-        let program = parse_properties_file(path)?;
-        assert_eq!(program.get_id(), "id1");
-        assert_eq!(program.get_command(), "command1 arg2");
-        assert!(program.need_superuser());
-        assert!(program.get_input().is_some());
-        assert_eq!(program.get_input().unwrap(), "arg2");
-        assert_eq!(program.get_env().get("ENVVAR").unwrap(), "env1");
+/// The `[log]` table: copy of the program output and lifecycle events on disk.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct Log {
+    /// Path template, `$id` is replaced with the program id
+    path: String,
+    /// Size of the file to rotate, in bytes
+    #[serde(default = "default_log_max_size")]
+    max_size: u64,
+    /// Number of rotated files to keep
+    #[serde(default = "default_log_rotate")]
+    rotate: u32,
+    #[serde(default = "default_log_timestamps")]
+    timestamps: bool,
+}
 
-        assert_eq!(program.get_title(), "title1");
-        assert_eq!(program.get_icon_on_path(), Some("/some/path/to/file"));
-        assert_eq!(program.get_icon_off_path(), program.get_icon_on_path());
-        Ok(())
+fn default_log_max_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_log_rotate() -> u32 {
+    5
+}
+
+fn default_log_timestamps() -> bool {
+    true
+}
+
+impl Log {
+    pub fn get_path(&self, id: &str) -> PathBuf {
+        self.replace_path(id).expect("log.path is validated")
     }
 
-    #[test]
-    fn read_config_without_args_and_env() -> io::Result<()> {
-        let temp_file = NamedTempFile::new()?;
-        let path = temp_file.path().to_str().unwrap();
-        // Write some data to the file
-        temp_file.as_file().write_all(
-            br#"
-          id = "id1"
-          command = "command1"
-        "#,
-        )?;
+    fn replace_path(&self, id: &str) -> io::Result<PathBuf> {
+        let vars = HashMap::from([("id".to_string(), id.to_string())]);
+        let path = replace_args(&self.path, &[&vars], Quoting::Plain).map_err(|error| {
+            let msg = format!("log.path is invalid: {}", error);
+            io::Error::new(ErrorKind::InvalidInput, msg)
+        })?;
+        Ok(expand_home(&path))
+    }
 
-        let program = parse_properties_file(path)?;
-        assert_eq!(program.get_id(), "id1");
-        assert_eq!(program.get_command(), "command1");
-        assert!(program.get_input().is_none());
-        assert!(program.get_env().is_empty());
-        assert_eq!(program.get_title(), "id1");
-        assert_eq!(program.get_icon_on_path(), None);
-        assert_eq!(program.get_icon_off_path(), program.get_icon_on_path());
-        Ok(())
+    pub fn get_max_size(&self) -> u64 {
+        self.max_size
+    }
+
+    pub fn get_rotate(&self) -> u32 {
+        self.rotate
+    }
+
+    pub fn need_timestamps(&self) -> bool {
+        self.timestamps
+    }
+}
+
+/// The `[health]` table: periodic check that the running program does its job.
+///
+#[derive(Debug, Clone, Deserialize)]
+pub struct Health {
+    #[serde(flatten)]
+    probe: Probe,
+    /// Delay between the checks, in seconds
+    #[serde(default = "default_health_interval")]
+    interval: f64,
+    /// Time limit of a single check, in seconds
+    #[serde(default = "default_health_timeout")]
+    timeout: f64,
+    /// Number of consecutive failed checks to consider the program unhealthy
+    #[serde(default = "default_health_failures")]
+    failures: u32,
+    /// Stop the unhealthy program, so the restart policy applies
+    #[serde(default)]
+    restart: bool,
+}
+
+/// How the program health is checked, selected by the `kind` key.
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Probe {
+    /// Connect to `host:port`
+    Tcp { address: String },
+    /// `GET` the `http://` URL and compare the response status
+    Http {
+        url: String,
+        #[serde(default = "default_expect_status")]
+        expect_status: u16,
+    },
+    /// Run the command, zero exit status means healthy
+    Command { command: String },
+}
+
+/// Command of the program: the line or the list of the program and its arguments
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum CommandLine {
+    /// Split like a shell does, the arg values are quoted
+    Line(String),
+    /// Taken as is, each element with the placeholders is a single argument
+    Argv(Vec<String>),
+}
+
+impl CommandLine {
+    /// Replace the placeholders left for the start and split the command
+    /// into the program and its arguments, or pass the line to the shell as is.
+    ///
+    pub fn to_argv(
+        &self,
+        args: &HashMap<String, String>,
+        shell: Option<&str>,
+    ) -> io::Result<Vec<String>> {
+        match self {
+            CommandLine::Line(line) => {
+                let line = replace_args(line, &[args], Quoting::Shell)?;
+                match shell {
+                    Some(shell) => Ok(vec![shell.to_string(), "-c".to_string(), line]),
+                    None => split_command(&line),
+                }
+            }
+            CommandLine::Argv(argv) => argv
+                .iter()
+                .map(|arg| replace_args(arg, &[args], Quoting::Plain))
+                .collect(),
+        }
+    }
+}
+
+/// Shell running the command line with pipes and redirections
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Shell {
+    Enabled(bool),
+    Path(String),
+}
+
+/// Command running the program as superuser
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Escalation {
+    Backend(Backend),
+    /// Command line prefix of a privileged helper
+    Helper(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    Pkexec,
+    /// `sudo -A`, asking the password with the `SUDO_ASKPASS` program
+    Sudo,
+    Doas,
+    Run0,
+}
+
+impl Escalation {
+    fn get_command(&self) -> Vec<String> {
+        let command: &[&str] = match self {
+            Escalation::Backend(Backend::Pkexec) => &["pkexec"],
+            Escalation::Backend(Backend::Sudo) => &["sudo", "-A"],
+            Escalation::Backend(Backend::Doas) => &["doas"],
+            Escalation::Backend(Backend::Run0) => &["run0"],
+            Escalation::Helper(command) => return command.clone(),
+        };
+        command.iter().map(|part| part.to_string()).collect()
+    }
+}
+
+/// Entry of the `[args]` table: the value or the table of the prompted arg
+///
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum Arg {
+    Value(String),
+    Prompt(Prompt),
+}
+
+/// Arg entered by the user before every start, like a one-time password
+///
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Prompt {
+    #[serde(skip)]
+    name: String,
+    prompt: bool,
+    /// Hide the entered text
+    #[serde(default)]
+    hidden: bool,
+    /// Text of the input field, the arg name by default
+    label: Option<String>,
+}
+
+impl Prompt {
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_label(&self) -> &str {
+        self.label.as_deref().unwrap_or(&self.name)
+    }
+
+    pub fn is_hidden(&self) -> bool {
+        self.hidden
+    }
+}
+
+/// Source of the arg value resolved at the program start instead of being written in the config
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum Secret {
+    /// `file:PATH`: the content of the file without the trailing newline
+    File(PathBuf),
+    /// `env:NAME`: the variable of the tray environment
+    Env(String),
+    /// `secret-service:key=value,...`: the desktop keyring item with these attributes
+    Service(Vec<(String, String)>),
+}
+
+impl Secret {
+    /// Parse the arg value referring to the secret, `None` for the plain value.
+    ///
+    fn parse(value: &str) -> Option<io::Result<Secret>> {
+        let (source, reference) = value.split_once(':')?;
+        let secret = match source {
+            "file" => Secret::File(expand_home(reference)),
+            "env" => Secret::Env(reference.to_string()),
+            "secret-service" => {
+                let pairs: Option<Vec<(&str, &str)>> = reference
+                    .split(',')
+                    .map(|pair| pair.split_once('='))
+                    .collect();
+                let Some(pairs) = pairs else {
+                    let msg = format!("'{}' must list key=value attributes", value);
+                    return Some(Err(io::Error::new(ErrorKind::InvalidInput, msg)));
+                };
+                Secret::Service(
+                    pairs
+                        .into_iter()
+                        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                        .collect(),
+                )
+            }
+            _ => return None,
+        };
+        let is_empty = match &secret {
+            Secret::File(path) => path.as_os_str().is_empty(),
+            Secret::Env(name) => name.is_empty(),
+            Secret::Service(attributes) => attributes.is_empty(),
+        };
+        if is_empty {
+            let msg = format!("'{}' refers to nothing", value);
+            return Some(Err(io::Error::new(ErrorKind::InvalidInput, msg)));
+        }
+        Some(Ok(secret))
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Secret::File(path) => write!(f, "file {}", path.display()),
+            Secret::Env(name) => write!(f, "environment variable {}", name),
+            Secret::Service(attributes) => {
+                let attributes: Vec<String> = attributes
+                    .iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                write!(f, "secret service item {}", attributes.join(","))
+            }
+        }
+    }
+}
+
+fn default_health_interval() -> f64 {
+    10.0
+}
+
+fn default_health_timeout() -> f64 {
+    5.0
+}
+
+fn default_health_failures() -> u32 {
+    3
+}
+
+fn default_expect_status() -> u16 {
+    200
+}
+
+impl Health {
+    pub fn get_probe(&self) -> &Probe {
+        &self.probe
+    }
+
+    pub fn get_interval(&self) -> Duration {
+        Duration::from_secs_f64(self.interval)
+    }
+
+    pub fn get_timeout(&self) -> Duration {
+        Duration::from_secs_f64(self.timeout)
+    }
+
+    pub fn get_failures(&self) -> u32 {
+        self.failures
+    }
+
+    pub fn need_restart(&self) -> bool {
+        self.restart
+    }
+
+    fn with_args(&self, vars: &[&HashMap<String, String>]) -> io::Result<Health> {
+        let replace = |field: &str, template: &str, quoting: Quoting| {
+            replace_args(template, vars, quoting).map_err(|error| {
+                let msg = format!("health.{} is invalid: {}", field, error);
+                io::Error::new(ErrorKind::InvalidInput, msg)
+            })
+        };
+        let probe = match &self.probe {
+            Probe::Tcp { address } => Probe::Tcp {
+                address: replace("address", address, Quoting::Plain)?,
+            },
+            Probe::Http { url, expect_status } => Probe::Http {
+                url: replace("url", url, Quoting::Plain)?,
+                expect_status: *expect_status,
+            },
+            Probe::Command { command } => {
+                let command = replace("command", command, Quoting::Shell)?;
+                split_command(&command).map_err(|error| {
+                    let msg = format!("health.command is invalid: {}", error);
+                    io::Error::new(ErrorKind::InvalidInput, msg)
+                })?;
+                Probe::Command { command }
+            }
+        };
+        Ok(Health {
+            probe,
+            ..self.clone()
+        })
+    }
+
+    fn validate(&self, restart: &Restart) -> io::Result<()> {
+        validate_seconds("health.interval", self.interval)?;
+        validate_seconds("health.timeout", self.timeout)?;
+        let msg = match &self.probe {
+            _ if self.interval == 0.0 => "health.interval must be positive",
+            _ if self.failures == 0 => "health.failures must be positive",
+            Probe::Http { url, .. } if !url.starts_with("http://") => {
+                "health.url must start with http://"
+            }
+            _ if self.restart && restart.get_policy() == RestartPolicy::Never => {
+                "health.restart requires a restart policy other than never"
+            }
+            _ => return Ok(()),
+        };
+        Err(io::Error::new(ErrorKind::InvalidInput, msg))
+    }
+}
+
+/// Resolve the path starting with `~/` against the home directory.
+///
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+fn parse_umask(value: &str) -> io::Result<u32> {
+    match u32::from_str_radix(value, 8) {
+        Ok(umask) if umask <= 0o777 => Ok(umask),
+        _ => {
+            let msg = format!("umask '{}' must be an octal number up to 777", value);
+            Err(io::Error::new(ErrorKind::InvalidInput, msg))
+        }
+    }
+}
+
+fn validate_seconds(name: &str, value: f64) -> io::Result<()> {
+    if value >= 0.0 && value.is_finite() {
+        return Ok(());
+    }
+    let msg = format!("{} must be a non-negative number of seconds", name);
+    Err(io::Error::new(ErrorKind::InvalidInput, msg))
+}
+
+#[derive(Default, Debug, Deserialize)]
+struct UI {
+    title: Option<String>,
+    #[serde(default)]
+    icons: Icons,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Icons {
+    on: Option<String>,
+    off: Option<String>,
+    starting: Option<String>,
+    unhealthy: Option<String>,
+}
+
+impl Program {
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    fn validate(&mut self) -> io::Result<()> {
+        if let Some(pattern) = self.ready_pattern.as_ref() {
+            if let Err(error) = Regex::new(pattern) {
+                let msg = format!("ready_pattern is invalid: {}", error);
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+        }
+        if let Some(timeout) = self.ready_timeout {
+            validate_seconds("ready_timeout", timeout)?;
+        }
+        self.restart.validate()?;
+        self.stop.validate()?;
+        self.limits.validate()?;
+        if let Some(cgroup) = self.cgroup.as_ref() {
+            cgroup.validate()?;
+        }
+        if let Some(health) = self.health.as_ref() {
+            health.validate(&self.restart)?;
+        }
+        if let Some(umask) = self.umask.as_ref() {
+            parse_umask(umask)?;
+        }
+        for (name, arg) in self.args.iter() {
+            let error = match arg {
+                Arg::Value(value) => Secret::parse(value).and_then(Result::err),
+                Arg::Prompt(prompt) if !prompt.prompt => Some(io::Error::new(
+                    ErrorKind::InvalidInput,
+                    "the table is only allowed with prompt = true",
+                )),
+                Arg::Prompt(_) => None,
+            };
+            if let Some(error) = error {
+                let msg = format!("arg '{}' is invalid: {}", name, error);
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+        }
+        if let (Some(_), CommandLine::Argv(_)) = (self.get_shell(), &self.command) {
+            let msg = "shell requires the command to be a string";
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+        self.account = self.resolve_account().map_err(|error| {
+            let msg = format!("account is invalid: {}", error);
+            io::Error::new(ErrorKind::InvalidInput, msg)
+        })?;
+        match self.escalation.as_ref() {
+            Some(_) if !self.superuser && !self.has_account() => {
+                let msg = "escalation requires superuser = true or the account to run as";
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+            Some(Escalation::Helper(command)) if command.is_empty() => {
+                let msg = "escalation command is empty";
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+            _ => {}
+        }
+        if !self.env_passthrough.is_empty() && !self.clear_env {
+            let msg = "env_passthrough requires clear_env = true";
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+        Ok(())
+    }
+
+    pub fn get_depends_on(&self) -> &[String] {
+        &self.depends_on
+    }
+
+    pub fn get_env(&self) -> &HashMap<String, String> {
+        &self.env
+    }
+
+    /// Merge the variables of the env files under the `env` table,
+    /// the later files override the earlier ones.
+    ///
+    fn load_env_files(&mut self, base_dir: &Path) -> io::Result<()> {
+        let mut vars = HashMap::new();
+        for path in self.env_file.iter() {
+            let path = replace_args(path, &[&self.get_plain_args(), &self.env], Quoting::Plain)
+                .map_err(|error| {
+                    let msg = format!("env_file is invalid: {}", error);
+                    io::Error::new(ErrorKind::InvalidInput, msg)
+                })?;
+            let path = base_dir.join(expand_home(&path));
+            vars.extend(dotenv::read(&path)?);
+        }
+        for (key, value) in vars {
+            self.env.entry(key).or_insert(value);
+        }
+        Ok(())
+    }
+
+    pub fn get_working_dir(&self) -> Option<PathBuf> {
+        let path = replace_args(
+            self.working_dir.as_ref()?,
+            &[&self.get_plain_args(), &self.env],
+            Quoting::Plain,
+        )
+        .expect("working_dir is validated");
+        Some(expand_home(&path))
+    }
+
+    pub fn get_umask(&self) -> Option<u32> {
+        parse_umask(self.umask.as_ref()?).ok()
+    }
+
+    pub fn need_clear_env(&self) -> bool {
+        self.clear_env
+    }
+
+    pub fn get_env_passthrough(&self) -> &[String] {
+        &self.env_passthrough
+    }
+
+    /// Args with the values written in the config.
+    /// The placeholders of the secret and prompted args are left for the launcher.
+    ///
+    fn get_plain_args(&self) -> HashMap<String, String> {
+        self.args
+            .iter()
+            .filter_map(|(name, arg)| match arg {
+                Arg::Value(value) if Secret::parse(value).is_none() => {
+                    Some((name.clone(), value.clone()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Replace the placeholders with the args written in the config, the `env` variables
+    /// or the environment variables, leaving the ones of the secret and prompted args
+    /// for the launcher.
+    ///
+    fn replace_plain_args(&self, template: &str, quoting: Quoting) -> io::Result<String> {
+        let args = self.get_plain_args();
+        let deferred: Vec<&str> = self
+            .args
+            .keys()
+            .filter(|name| !args.contains_key(*name))
+            .map(String::as_str)
+            .collect();
+        replace_known_args(template, &[&args, &self.env], &deferred, quoting)
+    }
+
+    /// Check that the placeholders of the templates are defined,
+    /// once the env files are loaded.
+    ///
+    fn validate_templates(&self) -> io::Result<()> {
+        let invalid = |field: &str, error: io::Error| {
+            let msg = format!("{} is invalid: {}", field, error);
+            io::Error::new(ErrorKind::InvalidInput, msg)
+        };
+        self.replace_command()
+            .map_err(|error| invalid("command", error))?;
+        if let Some(input) = self.input.as_ref() {
+            self.replace_plain_args(input, Quoting::Plain)
+                .map_err(|error| invalid("input", error))?;
+        }
+        for hook in Hook::ALL {
+            for command in self.hook_templates(hook) {
+                self.replace_plain_args(command, Quoting::Shell)
+                    .and_then(|command| split_command(&command))
+                    .map_err(|error| invalid(&hook.to_string(), error))?;
+            }
+        }
+
+        let vars = [&self.get_plain_args(), &self.env];
+        if let Some(dir) = self.working_dir.as_ref() {
+            replace_args(dir, &vars, Quoting::Plain)
+                .map_err(|error| invalid("working_dir", error))?;
+        }
+        if let Some(health) = self.health.as_ref() {
+            health.with_args(&vars)?;
+        }
+        if let Some(log) = self.log.as_ref() {
+            log.replace_path(&self.id)?;
+        }
+        Ok(())
+    }
+
+    /// Args with the values to be resolved at the program start.
+    ///
+    pub fn get_secrets(&self) -> Vec<(String, Secret)> {
+        self.args
+            .iter()
+            .filter_map(|(name, arg)| match arg {
+                Arg::Value(value) => Some((name.clone(), Secret::parse(value)?.ok()?)),
+                Arg::Prompt(_) => None,
+            })
+            .collect()
+    }
+
+    /// Args to be entered by the user before every start, sorted by name.
+    ///
+    pub fn get_prompts(&self) -> Vec<Prompt> {
+        let mut prompts: Vec<Prompt> = self
+            .args
+            .iter()
+            .filter_map(|(name, arg)| match arg {
+                Arg::Prompt(prompt) => Some(Prompt {
+                    name: name.clone(),
+                    ..prompt.clone()
+                }),
+                Arg::Value(_) => None,
+            })
+            .collect();
+        prompts.sort_by(|a, b| a.name.cmp(&b.name));
+        prompts
+    }
+
+    /// Replace the placeholders of the command written in the config,
+    /// checking that the line can be split unless it is run by the shell.
+    ///
+    fn replace_command(&self) -> io::Result<CommandLine> {
+        match &self.command {
+            CommandLine::Line(line) => {
+                let line = self.replace_plain_args(line, Quoting::Shell)?;
+                if self.get_shell().is_none() {
+                    split_command(&line)?;
+                }
+                Ok(CommandLine::Line(line))
+            }
+            CommandLine::Argv(argv) => {
+                let argv = argv
+                    .iter()
+                    .map(|arg| self.replace_plain_args(arg, Quoting::Plain))
+                    .collect::<io::Result<_>>()?;
+                Ok(CommandLine::Argv(argv))
+            }
+        }
+    }
+
+    pub fn get_command(&self) -> CommandLine {
+        self.replace_command().expect("command is validated")
+    }
+
+    /// Shell to run the command line with, if enabled.
+    ///
+    pub fn get_shell(&self) -> Option<String> {
+        match self.shell.as_ref()? {
+            Shell::Enabled(true) => Some(DEFAULT_SHELL.to_string()),
+            Shell::Enabled(false) => None,
+            Shell::Path(path) => Some(path.clone()),
+        }
+    }
+
+    /// Command line prefix running the program as superuser, if it needs to:
+    /// with `superuser = true` or to switch to the account the tray can't switch to.
+    ///
+    pub fn get_escalation(&self) -> Option<Vec<String>> {
+        let need_rights = self.superuser
+            || self
+                .get_account()
+                .is_some_and(|account| !account.can_switch());
+        match (need_rights, self.escalation.as_ref()) {
+            (false, _) => None,
+            (true, Some(escalation)) => Some(escalation.get_command()),
+            (true, None) => Some(Escalation::Backend(Backend::Pkexec).get_command()),
+        }
+    }
+
+    fn has_account(&self) -> bool {
+        self.user.is_some() || self.group.is_some() || !self.groups.is_empty()
+    }
+
+    fn resolve_account(&self) -> io::Result<Option<Account>> {
+        if !self.has_account() {
+            return Ok(None);
+        }
+        let account = Account::resolve(self.user.as_deref(), self.group.as_deref(), &self.groups)?;
+        Ok(Some(account))
+    }
+
+    /// Account to run the program as, if it is configured.
+    ///
+    pub fn get_account(&self) -> Option<&Account> {
+        self.account.as_ref()
+    }
+
+    pub fn need_pty(&self) -> bool {
+        self.pty
+    }
+
+    fn hook_templates(&self, hook: Hook) -> &[String] {
+        match hook {
+            Hook::PreStart => &self.pre_start,
+            Hook::PostStart => &self.post_start,
+            Hook::PreStop => &self.pre_stop,
+            Hook::PostStop => &self.post_stop,
+        }
+    }
+
+    pub fn get_hook_commands(&self, hook: Hook) -> Vec<String> {
+        self.hook_templates(hook)
+            .iter()
+            .map(|command| {
+                self.replace_plain_args(command, Quoting::Shell)
+                    .expect("hook commands are validated")
+            })
+            .collect()
+    }
+
+    pub fn get_input(&self) -> Option<String> {
+        let input = self.replace_plain_args(self.input.as_ref()?, Quoting::Plain);
+        Some(input.expect("input is validated"))
+    }
+
+    /// Longer lines of the program output are split, in bytes.
+    ///
+    pub fn get_max_line_length(&self) -> usize {
+        self.max_line_length
+    }
+
+    pub fn get_ready_pattern(&self) -> Option<Regex> {
+        let pattern = self.ready_pattern.as_ref()?;
+        Some(Regex::new(pattern).expect("ready_pattern is validated"))
+    }
+
+    pub fn get_ready_timeout(&self) -> Option<Duration> {
+        self.ready_timeout.map(Duration::from_secs_f64)
+    }
+
+    pub fn get_restart(&self) -> &Restart {
+        &self.restart
+    }
+
+    pub fn get_stop(&self) -> &Stop {
+        &self.stop
+    }
+
+    pub fn get_limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    pub fn get_cgroup(&self) -> Option<&Cgroup> {
+        self.cgroup.as_ref()
+    }
+
+    pub fn get_log(&self) -> Option<&Log> {
+        self.log.as_ref()
+    }
+
+    pub fn get_health(&self) -> Option<Health> {
+        let health = self.health.as_ref()?;
+        let health = health.with_args(&[&self.get_plain_args(), &self.env]);
+        Some(health.expect("health is validated"))
+    }
+
+    pub fn get_title(&self) -> &str {
+        self.ui
+            .title
+            .as_deref()
+            .map_or(self.id.as_str(), |title| title)
+    }
+
+    pub fn get_icon_on_path(&self) -> Option<&str> {
+        self.ui.icons.on.as_deref()
+    }
+
+    pub fn get_icon_off_path(&self) -> Option<&str> {
+        self.ui.icons.off.as_deref()
+    }
+
+    pub fn get_icon_starting_path(&self) -> Option<&str> {
+        self.ui.icons.starting.as_deref()
+    }
+
+    pub fn get_icon_unhealthy_path(&self) -> Option<&str> {
+        self.ui.icons.unhealthy.as_deref()
+    }
+}
+
+/// Read the programs of the config file: either a single program
+/// or a list of `[[program]]` entries.
+///
+pub fn parse_properties_file(file_path: &str) -> io::Result<Vec<Program>> {
+    let content = fs::read_to_string(file_path)?;
+    let mut programs = parse_programs(&content)?;
+    let base_dir = Path::new(file_path).parent().unwrap_or(Path::new(""));
+    for program in programs.iter_mut() {
+        program.load_env_files(base_dir)?;
+        program.validate_templates()?;
+    }
+    Ok(programs)
+}
+
+/// Read the programs of all config files, which must have unique ids.
+///
+pub fn parse_properties_files(file_paths: &[String]) -> io::Result<Vec<Program>> {
+    let mut programs = Vec::new();
+    for file_path in file_paths {
+        programs.extend(parse_properties_file(file_path)?);
+    }
+    validate_programs(&programs)?;
+    for program in programs.iter() {
+        for id in program.depends_on.iter() {
+            if !programs.iter().any(|other| other.id == *id) {
+                let msg = format!("Program '{}' depends on unknown '{}'", program.id, id);
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            }
+        }
+    }
+    Ok(programs)
+}
+
+pub(crate) fn parse_programs(content: &str) -> io::Result<Vec<Program>> {
+    let table: toml::Table = parse_toml(content)?;
+    let mut programs = match table.contains_key("program") {
+        true => parse_toml::<Programs>(content)?.program,
+        false => vec![parse_toml::<Program>(content)?],
+    };
+    for program in programs.iter_mut() {
+        program.validate()?;
+    }
+    validate_programs(&programs)?;
+    Ok(programs)
+}
+
+/// Read the config of a single program.
+///
+#[cfg(test)]
+pub(crate) fn parse_content(content: &str) -> io::Result<Program> {
+    let mut program: Program = parse_toml(content)?;
+    program.validate()?;
+    program.validate_templates()?;
+    Ok(program)
+}
+
+fn parse_toml<T: DeserializeOwned>(content: &str) -> io::Result<T> {
+    toml::from_str(content)
+        .map_err(|error| io::Error::new(ErrorKind::InvalidInput, error.message()))
+}
+
+fn validate_programs(programs: &[Program]) -> io::Result<()> {
+    if programs.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "No programs found"));
+    }
+    for (index, program) in programs.iter().enumerate() {
+        if programs[..index].iter().any(|other| other.id == program.id) {
+            let msg = format!("Duplicate program id '{}'", program.id);
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+    }
+    get_start_order(programs)?;
+    Ok(())
+}
+
+/// Order the programs so that each one follows its dependencies,
+/// failing on a dependency cycle. Unknown dependencies are ignored.
+///
+pub fn get_start_order(programs: &[Program]) -> io::Result<Vec<usize>> {
+    let mut order = Vec::with_capacity(programs.len());
+    for index in 0..programs.len() {
+        visit_dependencies(programs, index, &mut Vec::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+fn visit_dependencies(
+    programs: &[Program],
+    index: usize,
+    path: &mut Vec<usize>,
+    order: &mut Vec<usize>,
+) -> io::Result<()> {
+    if order.contains(&index) {
+        return Ok(());
+    }
+    if let Some(start) = path.iter().position(|visited| *visited == index) {
+        let cycle: Vec<&str> = path[start..]
+            .iter()
+            .chain([&index])
+            .map(|visited| programs[*visited].get_id())
+            .collect();
+        let msg = format!("Dependency cycle {}", cycle.join(" -> "));
+        return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+    }
+
+    path.push(index);
+    for id in programs[index].depends_on.iter() {
+        if let Some(dependency) = programs.iter().position(|program| program.id == *id) {
+            visit_dependencies(programs, dependency, path, order)?;
+        }
+    }
+    path.pop();
+    order.push(index);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn read_not_exists() {
+        let res = parse_properties_file("invalid");
+        assert!(res.is_err());
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn read_invalid_config() -> io::Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_str().unwrap();
+        // Write some data to the file
+        temp_file.as_file().write_all(br#"garbage"#)?;
+
+        let res = parse_properties_file(path);
+        assert!(res.is_err());
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn read_config() -> io::Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_str().unwrap();
+        // Write some data to the file
+        temp_file.as_file().write_all(
+            br#"
+          id = "id1"
+          command = "command1 $arg1"
+          superuser = true
+          pty = true
+          input = "arg2"
+          max_line_length = 100
+          
+          [args]
+          arg1 = "arg2"
+
+          [env]
+          ENVVAR = "env1"
+
+          [ui]
+          title = "title1"
+          
+          [ui.icons]
+          on = "/some/path/to/file"
+          off = "/some/path/to/file"
+        "#,
+        )?;
+
+        let programs = parse_properties_file(path)?;
+        assert_eq!(programs.len(), 1);
+        let program = &programs[0];
+        assert_eq!(program.get_id(), "id1");
+        assert_eq!(
+            program.get_command(),
+            CommandLine::Line("command1 arg2".to_string())
+        );
+        assert_eq!(program.get_escalation(), Some(vec!["pkexec".to_string()]));
+        assert!(program.need_pty());
+        assert!(program.get_input().is_some());
+        assert_eq!(program.get_input().unwrap(), "arg2");
+        assert_eq!(program.get_max_line_length(), 100);
+        assert_eq!(program.get_env().get("ENVVAR").unwrap(), "env1");
+
+        assert_eq!(program.get_title(), "title1");
+        assert_eq!(program.get_icon_on_path(), Some("/some/path/to/file"));
+        assert_eq!(program.get_icon_off_path(), program.get_icon_on_path());
+        Ok(())
+    }
+
+    #[test]
+    fn read_config_without_args_and_env() -> io::Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_str().unwrap();
+        // Write some data to the file
+        temp_file.as_file().write_all(
+            br#"
+          id = "id1"
+          command = "command1"
+        "#,
+        )?;
+
+        let programs = parse_properties_file(path)?;
+        assert_eq!(programs.len(), 1);
+        let program = &programs[0];
+        assert_eq!(program.get_id(), "id1");
+        assert_eq!(
+            program.get_command(),
+            CommandLine::Line("command1".to_string())
+        );
+        assert!(program.get_input().is_none());
+        assert!(!program.need_pty());
+        assert!(program.get_env().is_empty());
+        assert_eq!(program.get_title(), "id1");
+        assert_eq!(program.get_icon_on_path(), None);
+        assert_eq!(program.get_icon_off_path(), program.get_icon_on_path());
+        assert_eq!(program.get_max_line_length(), 4096);
+        assert!(program.get_log().is_none());
+        assert!(program.get_health().is_none());
+        assert!(program.get_ready_pattern().is_none());
+        assert!(program.get_ready_timeout().is_none());
+        assert!(Hook::ALL
+            .iter()
+            .all(|hook| program.get_hook_commands(*hook).is_empty()));
+        assert_eq!(program.get_restart().get_policy(), RestartPolicy::Never);
+        assert_eq!(program.get_stop().get_signal(), Signal::INT);
+        assert_eq!(program.get_stop().get_timeout(), Duration::from_secs(10));
+        Ok(())
+    }
+
+    #[test]
+    fn read_programs() -> io::Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_str().unwrap();
+        temp_file.as_file().write_all(
+            br#"
+          [[program]]
+          id = "id1"
+          command = "command1"
+
+          [[program]]
+          id = "id2"
+          command = "command2 $arg1"
+
+          [program.args]
+          arg1 = "arg2"
+
+          [program.ui]
+          title = "title2"
+        "#,
+        )?;
+
+        let programs = parse_properties_file(path)?;
+        assert_eq!(programs.len(), 2);
+        assert_eq!(programs[0].get_id(), "id1");
+        assert_eq!(programs[0].get_title(), "id1");
+        assert_eq!(
+            programs[1].get_command(),
+            CommandLine::Line("command2 arg2".to_string())
+        );
+        assert_eq!(programs[1].get_title(), "title2");
+
+        let other_file = NamedTempFile::new()?;
+        let other_path = other_file.path().to_str().unwrap();
+        other_file
+            .as_file()
+            .write_all(b"id = 'id3'\ncommand = 'command3'")?;
+        let paths = [path.to_string(), other_path.to_string()];
+        let programs = parse_properties_files(&paths)?;
+        let ids: Vec<&str> = programs.iter().map(|p| p.get_id()).collect();
+        assert_eq!(ids, ["id1", "id2", "id3"]);
+
+        let paths = [path.to_string(), path.to_string()];
+        let res = parse_properties_files(&paths);
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn read_invalid_programs() {
+        let res = parse_programs("program = []");
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let res = parse_programs(
+            r#"
+          [[program]]
+          id = "id1"
+          command = "command1"
+
+          [[program]]
+          id = "id1"
+          command = "command2"
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let res = parse_programs(
+            r#"
+          [[program]]
+          id = "id1"
+          command = "command1"
+
+          [[program]]
+          id = "id2"
+          command = "command2"
+          [program.restart]
+          backoff_max = -1
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn order_dependencies() -> io::Result<()> {
+        let programs = parse_programs(
+            r#"
+          [[program]]
+          id = "api"
+          command = "command1"
+          depends_on = ["tunnel", "auth"]
+
+          [[program]]
+          id = "auth"
+          command = "command2"
+          depends_on = ["tunnel"]
+
+          [[program]]
+          id = "tunnel"
+          command = "command3"
+        "#,
+        )?;
+        assert_eq!(programs[0].get_depends_on(), ["tunnel", "auth"]);
+        assert_eq!(get_start_order(&programs)?, [2, 1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn reject_dependency_cycle() -> io::Result<()> {
+        let temp_file = NamedTempFile::new()?;
+        let path = temp_file.path().to_str().unwrap();
+        temp_file.as_file().write_all(
+            br#"
+          [[program]]
+          id = "id1"
+          command = "command1"
+          depends_on = ["id3"]
+
+          [[program]]
+          id = "id2"
+          command = "command2"
+          depends_on = ["id1"]
+
+          [[program]]
+          id = "id3"
+          command = "command3"
+          depends_on = ["id2"]
+        "#,
+        )?;
+
+        let res = parse_properties_file(path);
+        let error = res.err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(
+            error.to_string(),
+            "Dependency cycle id1 -> id3 -> id2 -> id1"
+        );
+
+        let res = parse_programs("id = 'id1'\ncommand = 'command1'\ndepends_on = ['id1']");
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let other_file = NamedTempFile::new()?;
+        let other_path = other_file.path().to_str().unwrap();
+        other_file
+            .as_file()
+            .write_all(b"id = 'id1'\ncommand = 'command1'\ndepends_on = ['id2']")?;
+        let res = parse_properties_files(&[other_path.to_string()]);
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn read_hooks() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          pre_start = ["mkdir -p $dir", "mount $dir"]
+          post_stop = ["umount $dir"]
+
+          [args]
+          dir = "/run/user/share"
+        "#,
+        )?;
+
+        assert_eq!(
+            program.get_hook_commands(Hook::PreStart),
+            ["mkdir -p /run/user/share", "mount /run/user/share"]
+        );
+        assert!(program.get_hook_commands(Hook::PostStart).is_empty());
+        assert!(program.get_hook_commands(Hook::PreStop).is_empty());
+        assert_eq!(
+            program.get_hook_commands(Hook::PostStop),
+            ["umount /run/user/share"]
+        );
+        assert_eq!(Hook::PreStart.to_string(), "pre_start");
+        Ok(())
+    }
+
+    #[test]
+    fn read_environment() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          working_dir = "/srv/$name"
+          umask = "027"
+          clear_env = true
+          env_passthrough = ["DISPLAY", "XDG_*"]
+
+          [args]
+          name = "app"
+        "#,
+        )?;
+
+        assert_eq!(program.get_working_dir(), Some(PathBuf::from("/srv/app")));
+        assert_eq!(program.get_umask(), Some(0o027));
+        assert!(program.need_clear_env());
+        assert_eq!(program.get_env_passthrough(), ["DISPLAY", "XDG_*"]);
+
+        let program = parse_content("id = 'id1'\ncommand = 'command1'")?;
+        assert_eq!(program.get_working_dir(), None);
+        assert_eq!(program.get_umask(), None);
+        assert!(!program.need_clear_env());
+        Ok(())
+    }
+
+    #[test]
+    fn read_invalid_environment() {
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\numask = '0999'");
+        assert!(res.err().unwrap().to_string().contains("octal"));
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\numask = '1000'");
+        assert!(res.is_err());
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\nenv_passthrough = ['HOME']");
+        assert!(res.err().unwrap().to_string().contains("clear_env"));
+    }
+
+    #[test]
+    fn read_env_files() -> io::Result<()> {
+        let dir = tempfile::tempdir()?;
+        fs::write(
+            dir.path().join("common.env"),
+            "A=common\nB=common\nC=common\n",
+        )?;
+        fs::write(dir.path().join("app.env"), "# app\nB='app'\n")?;
+        let path = dir.path().join("app.toml");
+        fs::write(
+            &path,
+            r#"
+          id = "id1"
+          command = "command1"
+          env_file = ["common.env", "$name.env"]
+
+          [args]
+          name = "app"
+
+          [env]
+          C = "inline"
+        "#,
+        )?;
+
+        let programs = parse_properties_file(path.to_str().unwrap())?;
+        let env = programs[0].get_env();
+        assert_eq!(env.get("A").unwrap(), "common");
+        assert_eq!(env.get("B").unwrap(), "app");
+        assert_eq!(env.get("C").unwrap(), "inline");
+
+        fs::write(dir.path().join("app.env"), "B=1\nB 2\n")?;
+        let res = parse_properties_file(path.to_str().unwrap());
+        let msg = res.err().unwrap().to_string();
+        assert!(msg.ends_with("app.env:2: expected KEY=VALUE"), "{}", msg);
+        Ok(())
+    }
+
+    #[test]
+    fn read_placeholders() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1 --name $name --dir '${dir}' --level ${level:-info} --price 5$$"
+          input = "$name\n"
+          pre_start = ["sh -c 'echo $$PPID $NAME'"]
+
+          [args]
+          name = "John Doe"
+          dir = "it's"
+          otp = { prompt = true }
+
+          [env]
+          NAME = "env name"
+        "#,
+        )?;
+        assert_eq!(
+            program.get_command(),
+            CommandLine::Line(
+                "command1 --name 'John Doe' --dir 'it'\\''s' --level info --price 5$$".to_string(),
+            )
+        );
+        assert_eq!(program.get_input().unwrap(), "John Doe\n");
+        assert_eq!(
+            program.get_hook_commands(Hook::PreStart),
+            ["sh -c 'echo $$PPID env name'"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_undefined_placeholders() {
+        let res = parse_content("id = 'id1'\ncommand = 'command1 $PROGRAM_TRAY_TEST_UNDEFINED'");
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "command is invalid: undefined placeholder '$PROGRAM_TRAY_TEST_UNDEFINED'"
+        );
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\npost_stop = ['rm ${dir']");
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "post_stop is invalid: missing closing brace of '${dir'"
+        );
+
+        // the prompted args are known only at the start
+        let res = parse_content(
+            r#"
+          id = "id1"
+          command = "command1 $otp"
+          working_dir = "/tmp/$otp"
+
+          [args]
+          otp = { prompt = true }
+        "#,
+        );
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "working_dir is invalid: undefined placeholder '$otp'"
+        );
+    }
+
+    #[test]
+    fn read_command_argv() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = ["command1", "--name", "$name", "--otp=${otp}", "it's $$5"]
+
+          [args]
+          name = "John 'Doe'"
+          otp = { prompt = true }
+        "#,
+        )?;
+        let command = program.get_command();
+        assert_eq!(
+            command,
+            CommandLine::Argv(vec![
+                "command1".to_string(),
+                "--name".to_string(),
+                "John 'Doe'".to_string(),
+                "--otp=${otp}".to_string(),
+                "it's $$5".to_string(),
+            ])
+        );
+        let args = HashMap::from([("otp".to_string(), "1 2".to_string())]);
+        assert_eq!(
+            command.to_argv(&args, None)?,
+            ["command1", "--name", "John 'Doe'", "--otp=1 2", "it's $5"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn read_invalid_command() {
+        let res = parse_content("id = 'id1'\ncommand = \"command1 'arg\"");
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "command is invalid: unclosed quote or trailing backslash in 'command1 'arg'"
+        );
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\npre_stop = ['echo \"a']");
+        assert!(res
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("pre_stop is invalid"));
+
+        let res = parse_content(
+            "id = 'id1'\ncommand = 'command1'\n[health]\nkind = 'command'\ncommand = 'check\\'",
+        );
+        assert!(res
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("health.command is invalid"));
+
+        let res = parse_content("id = 'id1'\ncommand = 1");
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn read_shell() -> io::Result<()> {
+        let program = parse_content("id = 'id1'\ncommand = 'command1 | tee \"out'\nshell = true")?;
+        assert_eq!(program.get_shell().as_deref(), Some("/bin/sh"));
+        let program = parse_content("id = 'id1'\ncommand = 'command1'\nshell = '/bin/bash'")?;
+        assert_eq!(program.get_shell().as_deref(), Some("/bin/bash"));
+        let program = parse_content("id = 'id1'\ncommand = 'command1'\nshell = false")?;
+        assert_eq!(program.get_shell(), None);
+        let program = parse_content("id = 'id1'\ncommand = 'command1'")?;
+        assert_eq!(program.get_shell(), None);
+
+        let res = parse_content("id = 'id1'\ncommand = ['command1']\nshell = true");
+        assert!(res.err().unwrap().to_string().contains("shell"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_escalation() -> io::Result<()> {
+        let program = parse_content("id = 'id1'\ncommand = 'command1'\nsuperuser = true")?;
+        assert_eq!(program.get_escalation(), Some(vec!["pkexec".to_string()]));
+        let program = parse_content(
+            "id = 'id1'\ncommand = 'command1'\nsuperuser = true\nescalation = 'sudo'",
+        )?;
+        assert_eq!(
+            program.get_escalation(),
+            Some(vec!["sudo".to_string(), "-A".to_string()])
+        );
+        let program = parse_content(
+            "id = 'id1'\ncommand = 'command1'\nsuperuser = true\nescalation = ['helper', 'run']",
+        )?;
+        assert_eq!(
+            program.get_escalation(),
+            Some(vec!["helper".to_string(), "run".to_string()])
+        );
+        let program = parse_content("id = 'id1'\ncommand = 'command1'")?;
+        assert_eq!(program.get_escalation(), None);
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\nescalation = 'doas'");
+        assert!(res.err().unwrap().to_string().contains("superuser"));
+        let res =
+            parse_content("id = 'id1'\ncommand = 'command1'\nsuperuser = true\nescalation = 'su'");
+        assert!(res.is_err());
+        let res =
+            parse_content("id = 'id1'\ncommand = 'command1'\nsuperuser = true\nescalation = []");
+        assert!(res.err().unwrap().to_string().contains("empty"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_account() -> io::Result<()> {
+        let program = parse_content("id = 'id1'\ncommand = 'command1'")?;
+        assert_eq!(program.get_account(), None);
+        let program = parse_content("id = 'id1'\ncommand = 'command1'\nuser = 'root'")?;
+        assert_eq!(
+            program.get_account(),
+            Some(&Account::resolve(Some("root"), None, &[])?)
+        );
+        parse_content("id = 'id1'\ncommand = 'command1'\ngroup = 'root'\nescalation = 'sudo'")?;
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\nuser = 'no-such-user'");
+        assert_eq!(
+            res.err().unwrap().to_string(),
+            "account is invalid: user 'no-such-user' does not exist"
+        );
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\ngroups = ['no-such-group']");
+        assert!(res.err().unwrap().to_string().contains("no-such-group"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_secrets() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1 --user $user --password $password"
+          input = "$token"
+
+          [args]
+          user = "user1"
+          password = "file:/run/secrets/password"
+          token = "secret-service:service=api, user=user1"
+          url = "http://localhost"
+        "#,
+        )?;
+
+        // the secrets are resolved at the start
+        assert_eq!(
+            program.get_command(),
+            CommandLine::Line("command1 --user user1 --password $password".to_string())
+        );
+        assert_eq!(program.get_input().unwrap(), "$token");
+        let mut secrets = program.get_secrets();
+        secrets.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            secrets,
+            [
+                (
+                    "password".to_string(),
+                    Secret::File(PathBuf::from("/run/secrets/password"))
+                ),
+                (
+                    "token".to_string(),
+                    Secret::Service(vec![
+                        ("service".to_string(), "api".to_string()),
+                        ("user".to_string(), "user1".to_string())
+                    ])
+                ),
+            ]
+        );
+
+        let res = parse_content("id = 'id1'\ncommand = 'c'\n[args]\na = 'env:'");
+        assert!(res
+            .err()
+            .unwrap()
+            .to_string()
+            .contains("arg 'a' is invalid"));
+        let res = parse_content("id = 'id1'\ncommand = 'c'\n[args]\na = 'secret-service:x'");
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_prompts() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1 --user $user --otp $otp"
+          input = "$password"
+
+          [args]
+          user = "user1"
+          password = { prompt = true, hidden = true }
+
+          [args.otp]
+          prompt = true
+          label = "One-time code"
+        "#,
+        )?;
+
+        // the prompted values are substituted at the start
+        assert_eq!(
+            program.get_command(),
+            CommandLine::Line("command1 --user user1 --otp $otp".to_string())
+        );
+        assert_eq!(program.get_input().unwrap(), "$password");
+        let prompts = program.get_prompts();
+        assert_eq!(prompts.len(), 2);
+        assert_eq!(prompts[0].get_name(), "otp");
+        assert_eq!(prompts[0].get_label(), "One-time code");
+        assert!(!prompts[0].is_hidden());
+        assert_eq!(prompts[1].get_name(), "password");
+        assert_eq!(prompts[1].get_label(), "password");
+        assert!(prompts[1].is_hidden());
+        assert!(program.get_secrets().is_empty());
+
+        let res = parse_content("id = 'id1'\ncommand = 'c'\n[args]\na = { prompt = false }");
+        assert!(res.err().unwrap().to_string().contains("prompt = true"));
+        let res = parse_content("id = 'id1'\ncommand = 'c'\n[args]\na = { prompt = true, x = 1 }");
+        assert!(res.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_stop() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [stop]
+          signal = "SIGTERM"
+          timeout = 2.5
+          kill_descendants = true
+        "#,
+        )?;
+
+        let stop = program.get_stop();
+        assert_eq!(stop.get_signal(), Signal::TERM);
+        assert_eq!(stop.get_timeout(), Duration::from_millis(2500));
+        assert!(stop.need_kill_descendants());
+        Ok(())
+    }
+
+    #[test]
+    fn read_limits() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [limits]
+          open_files = 1024
+          core_size = 0
+          nice = 10
+          io_priority = 7
+        "#,
+        )?;
+
+        let limits = program.get_limits();
+        assert_eq!(limits.get_open_files(), Some(1024));
+        assert_eq!(limits.get_address_space(), None);
+        assert_eq!(limits.get_core_size(), Some(0));
+        assert_eq!(limits.get_nice(), Some(10));
+        assert_eq!(limits.get_io_priority(), Some((IoClass::BestEffort, 7)));
+        assert_eq!(
+            limits.to_string(),
+            "open files 1024, core size 0 bytes, nice 10, I/O best-effort 7"
+        );
+
+        let program = parse_content("id = 'id1'\ncommand = 'command1'")?;
+        assert!(program.get_limits().is_empty());
+        let program =
+            parse_content("id = 'id1'\ncommand = 'command1'\n[limits]\nio_class = 'idle'")?;
+        assert_eq!(program.get_limits().to_string(), "I/O idle");
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\n[limits]\nnice = -21");
+        assert!(res.err().unwrap().to_string().contains("limits.nice"));
+        let res = parse_content(
+            "id = 'id1'\ncommand = 'command1'\n[limits]\nio_class = 'idle'\nio_priority = 1",
+        );
+        assert!(res.err().unwrap().to_string().contains("idle"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_cgroup() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [cgroup]
+          memory_max = 1073741824
+          cpu_quota = 150
+        "#,
+        )?;
+
+        let cgroup = program.get_cgroup().unwrap();
+        assert_eq!(cgroup.get_mode(), CgroupMode::Systemd);
+        assert_eq!(cgroup.get_parent(), None);
+        assert_eq!(cgroup.get_memory_max(), Some(1073741824));
+        assert_eq!(cgroup.get_cpu_quota(), Some(150));
+
+        let program = parse_content(
+            "id = 'id1'\ncommand = 'command1'\n[cgroup]\nmode = 'direct'\nparent = '/sys/fs/cgroup/tray'",
+        )?;
+        let cgroup = program.get_cgroup().unwrap();
+        assert_eq!(cgroup.get_mode(), CgroupMode::Direct);
+        assert_eq!(
+            cgroup.get_parent(),
+            Some(PathBuf::from("/sys/fs/cgroup/tray"))
+        );
+        assert_eq!(cgroup.get_memory_max(), None);
+
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\n[cgroup]\nmode = 'direct'");
+        assert!(res.err().unwrap().to_string().contains("cgroup.parent"));
+        let res = parse_content("id = 'id1'\ncommand = 'command1'\n[cgroup]\ncpu_quota = 0");
+        assert!(res.err().unwrap().to_string().contains("cgroup.cpu_quota"));
+        Ok(())
+    }
+
+    #[test]
+    fn read_log() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [log]
+          path = "/var/log/$id/output.log"
+          max_size = 1000
+        "#,
+        )?;
+
+        let log = program.get_log().unwrap();
+        assert_eq!(
+            log.get_path("id1"),
+            PathBuf::from("/var/log/id1/output.log")
+        );
+        assert_eq!(log.get_max_size(), 1000);
+        assert_eq!(log.get_rotate(), 5);
+        assert!(log.need_timestamps());
+        Ok(())
+    }
+
+    #[test]
+    fn read_ready_pattern() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          ready_pattern = "Listening on \\d+"
+          ready_timeout = 1.5
+        "#,
+        )?;
+        let pattern = program.get_ready_pattern().unwrap();
+        assert!(pattern.is_match("Listening on 8080"));
+        assert!(!pattern.is_match("Listening on port"));
+        assert_eq!(
+            program.get_ready_timeout(),
+            Some(Duration::from_millis(1500))
+        );
+
+        let res = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          ready_pattern = "Listening on ("
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+        Ok(())
+    }
+
+    #[test]
+    fn read_health() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [args]
+          port = "8080"
+
+          [restart]
+          policy = "on-failure"
+
+          [health]
+          kind = "http"
+          url = "http://localhost:$port/health"
+          expect_status = 204
+          interval = 2
+          restart = true
+        "#,
+        )?;
+
+        let health = program.get_health().unwrap();
+        assert_eq!(
+            *health.get_probe(),
+            Probe::Http {
+                url: "http://localhost:8080/health".to_string(),
+                expect_status: 204
+            }
+        );
+        assert_eq!(health.get_interval(), Duration::from_secs(2));
+        assert_eq!(health.get_timeout(), Duration::from_secs(5));
+        assert_eq!(health.get_failures(), 3);
+        assert!(health.need_restart());
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [health]
+          kind = "tcp"
+          address = "localhost:22"
+        "#,
+        )?;
+        let health = program.get_health().unwrap();
+        assert_eq!(
+            *health.get_probe(),
+            Probe::Tcp {
+                address: "localhost:22".to_string()
+            }
+        );
+        assert!(!health.need_restart());
+        Ok(())
+    }
+
+    #[test]
+    fn read_invalid_health() {
+        for health in [
+            "kind = 'ping'",
+            "kind = 'tcp'",
+            "kind = 'http'\nurl = 'https://localhost'",
+            "kind = 'command'\ncommand = 'true'\ninterval = 0",
+            "kind = 'command'\ncommand = 'true'\nfailures = 0",
+            "kind = 'command'\ncommand = 'true'\nrestart = true",
+        ] {
+            let content = format!("id = 'id1'\ncommand = 'command1'\n[health]\n{}", health);
+            let res = parse_content(&content);
+            assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn parse_signal() {
+        assert_eq!(Signal::from_str("hup"), Ok(Signal(libc::SIGHUP)));
+        assert_eq!(Signal::from_str("QUIT"), Ok(Signal(libc::SIGQUIT)));
+        assert!(Signal::from_str("SIGNOPE").is_err());
+        assert_eq!(Signal::TERM.to_string(), "SIGTERM");
+        assert_eq!(Signal(42).to_string(), "signal 42");
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [stop]
+          signal = 12
+        "#,
+        )
+        .unwrap();
+        assert_eq!(program.get_stop().get_signal(), Signal(libc::SIGUSR2));
+
+        let res = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [stop]
+          signal = 100
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn read_restart() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [restart]
+          policy = "on-failure"
+          max_retries = 5
+          backoff_base = 0.5
+          backoff_max = 3
+        "#,
+        )?;
+
+        let restart = program.get_restart();
+        assert_eq!(restart.get_policy(), RestartPolicy::OnFailure);
+        assert_eq!(restart.get_max_retries(), 5);
+        assert!(restart.is_required(false));
+        assert!(!restart.is_required(true));
+        assert_eq!(restart.get_backoff(1), Duration::from_millis(500));
+        assert_eq!(restart.get_backoff(3), Duration::from_secs(2));
+        assert_eq!(restart.get_backoff(4), Duration::from_secs(3));
+        assert_eq!(restart.get_backoff(100), Duration::from_secs(3));
+        Ok(())
+    }
+
+    #[test]
+    fn read_invalid_restart() {
+        let res = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [restart]
+          policy = "sometimes"
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
+
+        let res = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [restart]
+          backoff_base = -1
+        "#,
+        );
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::InvalidInput);
     }
 }
@@ -0,0 +1,183 @@
+use std::io::ErrorKind;
+use std::path::Path;
+use std::{fs, io};
+
+/// Read the `KEY=VALUE` pairs of the dotenv file, in the order of the file.
+/// The error points at the failing line as `path:line: reason`.
+///
+pub fn read(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let content = fs::read_to_string(path).map_err(|error| {
+        let msg = format!("Failed to read env_file {}: {}", path.display(), error);
+        io::Error::new(error.kind(), msg)
+    })?;
+    parse(&content).map_err(|(line, reason)| {
+        let msg = format!("{}:{}: {}", path.display(), line, reason);
+        io::Error::new(ErrorKind::InvalidData, msg)
+    })
+}
+
+/// Parse the dotenv content:
+/// - blank lines and lines starting with `#` are skipped, `export ` prefix is allowed;
+/// - `'single quoted'` values are taken as is;
+/// - `"double quoted"` values support `\n`, `\t`, `\"`, `\\` and `\$` escapes;
+/// - unquoted values are trimmed and end at ` #` comment.
+///
+/// The error is the line number with the reason.
+///
+fn parse(content: &str) -> Result<Vec<(String, String)>, (usize, String)> {
+    let mut vars = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key, value) = parse_line(line).map_err(|reason| (index + 1, reason))?;
+        vars.push((key, value));
+    }
+    Ok(vars)
+}
+
+fn parse_line(line: &str) -> Result<(String, String), String> {
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| "expected KEY=VALUE".to_string())?;
+    let key = key.trim();
+    if !is_valid_key(key) {
+        return Err(format!("invalid variable name '{}'", key));
+    }
+
+    let value = value.trim_start();
+    let (value, rest) = match value.chars().next() {
+        Some('\'') => parse_single_quoted(&value[1..])?,
+        Some('"') => parse_double_quoted(&value[1..])?,
+        _ => return Ok((key.to_string(), parse_unquoted(value))),
+    };
+    let rest = rest.trim_start();
+    if !rest.is_empty() && !rest.starts_with('#') {
+        return Err(format!("unexpected '{}' after the quoted value", rest));
+    }
+    Ok((key.to_string(), value))
+}
+
+fn is_valid_key(key: &str) -> bool {
+    let mut chars = key.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn parse_unquoted(value: &str) -> String {
+    let end = value
+        .char_indices()
+        .find(|(index, c)| *c == '#' && value[..*index].ends_with(char::is_whitespace))
+        .map_or(value.len(), |(index, _)| index);
+    value[..end].trim_end().to_string()
+}
+
+/// Take the value till the closing quote, returning it with the rest of the line.
+///
+fn parse_single_quoted(value: &str) -> Result<(String, &str), String> {
+    match value.find('\'') {
+        Some(end) => Ok((value[..end].to_string(), &value[end + 1..])),
+        None => Err("missing closing single quote".to_string()),
+    }
+}
+
+fn parse_double_quoted(value: &str) -> Result<(String, &str), String> {
+    let mut result = String::new();
+    let mut chars = value.char_indices();
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '"' => return Ok((result, &value[index + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c @ ('"' | '\\' | '$')) => result.push(c),
+                Some(c) => return Err(format!("unknown escape sequence '\\{}'", c)),
+                None => break,
+            },
+            c => result.push(c),
+        }
+    }
+    Err("missing closing double quote".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn pair(key: &str, value: &str) -> (String, String) {
+        (key.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn parse_values() {
+        let content = r#"
+# database settings
+DB_HOST=localhost
+export DB_PORT = 5432
+DB_NAME=app # comment
+DB_PASSWORD='p#ss "word"'
+GREETING="Hello,\n\"world\" \$HOME" # comment
+URL=http://host/#anchor
+EMPTY=
+"#;
+        assert_eq!(
+            parse(content).unwrap(),
+            [
+                pair("DB_HOST", "localhost"),
+                pair("DB_PORT", "5432"),
+                pair("DB_NAME", "app"),
+                pair("DB_PASSWORD", "p#ss \"word\""),
+                pair("GREETING", "Hello,\n\"world\" $HOME"),
+                pair("URL", "http://host/#anchor"),
+                pair("EMPTY", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(
+            parse("A=1\n\nB").err().unwrap(),
+            (3, "expected KEY=VALUE".to_string())
+        );
+        assert_eq!(
+            parse("1A=1").err().unwrap(),
+            (1, "invalid variable name '1A'".to_string())
+        );
+        assert_eq!(
+            parse("A='1").err().unwrap(),
+            (1, "missing closing single quote".to_string())
+        );
+        assert_eq!(
+            parse("A=\"1\\\"").err().unwrap(),
+            (1, "missing closing double quote".to_string())
+        );
+        assert_eq!(
+            parse("A=\"\\x\"").err().unwrap(),
+            (1, "unknown escape sequence '\\x'".to_string())
+        );
+        assert_eq!(
+            parse("A='1' 2").err().unwrap(),
+            (1, "unexpected '2' after the quoted value".to_string())
+        );
+    }
+
+    #[test]
+    fn read_file() -> io::Result<()> {
+        let mut file = NamedTempFile::new()?;
+        writeln!(file, "A=1\nB")?;
+        let res = read(file.path());
+        let msg = format!("{}:2: expected KEY=VALUE", file.path().display());
+        assert_eq!(res.err().unwrap().to_string(), msg);
+
+        let res = read(Path::new("/nonexistent/.env"));
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::NotFound);
+        Ok(())
+    }
+}
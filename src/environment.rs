@@ -0,0 +1,147 @@
+use crate::config::Program;
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Working directory, umask and variables of the program,
+/// shared by its hook and health check commands
+///
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    working_dir: Option<PathBuf>,
+    umask: Option<u32>,
+    clear_env: bool,
+    passthrough: Vec<String>,
+    vars: HashMap<String, String>,
+}
+
+impl Environment {
+    pub fn new(program: &Program) -> Self {
+        Environment {
+            working_dir: program.get_working_dir(),
+            umask: program.get_umask(),
+            clear_env: program.need_clear_env(),
+            passthrough: program.get_env_passthrough().to_vec(),
+            vars: program.get_env().clone(),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_vars(vars: HashMap<String, String>) -> Self {
+        Environment {
+            vars,
+            ..Self::default()
+        }
+    }
+
+    pub fn get_working_dir(&self) -> Option<&Path> {
+        self.working_dir.as_deref()
+    }
+
+    pub fn get_umask(&self) -> Option<u32> {
+        self.umask
+    }
+
+    /// Variables the program gets on top of the inherited ones: the passed through
+    /// and the configured ones, for the commands which replace the inherited environment.
+    ///
+    pub fn get_explicit_vars(&self) -> Vec<(String, String)> {
+        let mut vars: Vec<(String, String)> = std::env::vars_os()
+            .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+            .filter(|(name, _)| self.is_passed(name))
+            .collect();
+        vars.extend(
+            self.vars
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone())),
+        );
+        vars
+    }
+
+    /// Setup the command to run in the environment.
+    /// The inherited variables are dropped if `clear_env` is set, except the passed through ones.
+    ///
+    pub fn apply(&self, command: &mut Command) {
+        if let Some(dir) = self.working_dir.as_ref() {
+            command.current_dir(dir);
+        }
+
+        if self.clear_env {
+            command.env_clear();
+            for (name, value) in std::env::vars_os() {
+                if name.to_str().is_some_and(|name| self.is_passed(name)) {
+                    command.env(name, value);
+                }
+            }
+        }
+        command.envs(self.vars.iter());
+
+        if let Some(umask) = self.umask {
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(umask as libc::mode_t);
+                    Ok::<(), io::Error>(())
+                });
+            }
+        }
+    }
+
+    fn is_passed(&self, name: &str) -> bool {
+        self.passthrough
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_content;
+
+    fn run(env: &Environment, script: &str) -> String {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        env.apply(&mut command);
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        String::from_utf8(output.stdout).unwrap().trim().to_string()
+    }
+
+    #[test]
+    fn apply_environment() {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          working_dir = "/tmp"
+          umask = "027"
+          clear_env = true
+          env_passthrough = ["HOM*"]
+
+          [env]
+          VAR1 = "val1"
+        "#,
+        )
+        .unwrap();
+        let env = Environment::new(&program);
+
+        assert_eq!(run(&env, "pwd"), "/tmp");
+        assert_eq!(run(&env, "umask"), "0027");
+        assert_eq!(run(&env, "echo $VAR1"), "val1");
+        assert_eq!(run(&env, "echo \"$HOME\""), std::env::var("HOME").unwrap());
+        assert_eq!(run(&env, "echo \"$CARGO_PKG_NAME\""), "");
+    }
+
+    #[test]
+    fn inherit_environment() {
+        let env = Environment::with_vars(HashMap::from([("VAR1".into(), "val1".into())]));
+        assert_eq!(run(&env, "echo $VAR1"), "val1");
+        let name = std::env::var("CARGO_PKG_NAME").unwrap();
+        assert_eq!(run(&env, "echo $CARGO_PKG_NAME"), name);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::config::Signal;
+use crate::environment::Environment;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Script run with the superuser rights in place of the program: it execs the program
+/// and serves the stop requests read from the control pipe in background.
+/// A request is the signal number followed by the pids of the escaped descendants,
+/// which are only signalled if they are still in the session of the program.
+/// The escalation command resets the environment, so the script sets the variables,
+/// the umask and the working directory of the program itself.
+///
+const HELPER_SCRIPT: &str = r#"ctl=$1 vars=$2 mask=$3 dir=$4
+shift 4
+(
+    session() {
+        read -r stat < "/proc/$1/stat" || return
+        set -- ${stat##*) }
+        echo "$4"
+    }
+    own=$(session self)
+    while read -r signal pids; do
+        trap '' "$signal"
+        for pid in $pids; do
+            [ "$(session "$pid")" = "$own" ] && kill -"$signal" "$pid"
+        done
+        kill -"$signal" 0
+    done
+) < "$ctl" > /dev/null 2>&1 &
+while IFS= read -r var; do export "$var"; done < "$vars"
+[ -z "$mask" ] || umask "$mask"
+[ -z "$dir" ] || cd "$dir" || exit
+exec "$@""#;
+
+/// Name of the control pipe in the session directory
+const CONTROL_PIPE: &str = "control";
+
+/// Name of the file with the variables of the program in the session directory
+const VARS_FILE: &str = "environment";
+
+/// Counter of the sessions opened by the tray, naming their directories
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// Elevated session of the running program: the control pipe of its helper script.
+/// The helper quits when the pipe is closed, so the session lives till it is dropped.
+///
+pub struct Session {
+    dir: PathBuf,
+    pipe: File,
+}
+
+impl Session {
+    /// Create the control pipe in a directory accessible to the user only.
+    ///
+    pub fn open() -> io::Result<Session> {
+        let base = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let number = SESSIONS.fetch_add(1, Ordering::Relaxed);
+        let dir = base.join(format!("program-tray-{}-{}", std::process::id(), number));
+        fs::DirBuilder::new().mode(0o700).create(&dir)?;
+
+        let path = dir.join(CONTROL_PIPE);
+        let name = std::ffi::CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::mkfifo(name.as_ptr(), 0o600) } != 0 {
+            let e = io::Error::last_os_error();
+            let _ = fs::remove_dir(&dir);
+            return Err(e);
+        }
+        // opened for reading too, so neither side blocks till the other one opens it
+        let pipe = OpenOptions::new().read(true).write(true).open(&path);
+        match pipe {
+            Ok(pipe) => Ok(Session { dir, pipe }),
+            Err(e) => {
+                let _ = fs::remove_dir_all(&dir);
+                Err(e)
+            }
+        }
+    }
+
+    /// Command line running the program with the escalation command through the helper,
+    /// which sets up the environment of the program.
+    /// The variables are passed in a file, since the command line is visible to other users.
+    ///
+    pub fn wrap(
+        &self,
+        escalation: &[String],
+        env: &Environment,
+        argv: &[String],
+    ) -> io::Result<Vec<String>> {
+        let mut vars = String::new();
+        for (name, value) in env.get_explicit_vars() {
+            if value.contains('\n') {
+                let msg = format!("variable {} with a line break can't be passed", name);
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, msg));
+            }
+            vars.push_str(&format!("{}={}\n", name, value));
+        }
+        let path = self.dir.join(VARS_FILE);
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&path)?
+            .write_all(vars.as_bytes())?;
+
+        let setup = [
+            self.dir.join(CONTROL_PIPE).to_string_lossy().into_owned(),
+            path.to_string_lossy().into_owned(),
+            env.get_umask()
+                .map_or(String::new(), |umask| format!("{:03o}", umask)),
+            env.get_working_dir()
+                .map_or(String::new(), |dir| dir.to_string_lossy().into_owned()),
+        ];
+        Ok(escalation
+            .iter()
+            .cloned()
+            .chain(["/bin/sh", "-c", HELPER_SCRIPT, "program-tray"].map(String::from))
+            .chain(setup)
+            .chain(argv.iter().cloned())
+            .collect())
+    }
+
+    /// Ask the helper to send the signal to the process group of the program
+    /// and to the escaped descendants, without asking for the rights again.
+    ///
+    pub fn kill(&self, signal: Signal, escaped: &[u32]) -> io::Result<()> {
+        let mut request = signal.get_number().to_string();
+        for pid in escaped {
+            request.push_str(&format!(" {}", pid));
+        }
+        request.push('\n');
+        (&self.pipe).write_all(request.as_bytes())
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_content;
+    use std::os::unix::process::{CommandExt, ExitStatusExt};
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn stop_through_helper() -> io::Result<()> {
+        let session = Session::open()?;
+        let dir = session.dir.clone();
+        // `env` stands for the escalation command, which execs the helper
+        let argv = session.wrap(
+            &["env".to_string()],
+            &Environment::default(),
+            &["sleep".to_string(), "60".to_string()],
+        )?;
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]).process_group(0);
+        let mut child = command.spawn()?;
+
+        thread::sleep(Duration::from_millis(300));
+        session.kill(Signal::TERM, &[])?;
+        let status = child.wait()?;
+        assert_eq!(status.signal(), Some(libc::SIGTERM));
+
+        drop(session);
+        assert!(!dir.exists());
+        Ok(())
+    }
+
+    #[test]
+    fn pass_environment_through_helper() -> io::Result<()> {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+          working_dir = "/tmp"
+          umask = "027"
+
+          [env]
+          VAR1 = "two  'words'"
+        "#,
+        )?;
+        let session = Session::open()?;
+        // `env -i` stands for the escalation command resetting the environment
+        let argv = session.wrap(
+            &["env".to_string(), "-i".to_string()],
+            &Environment::new(&program),
+            &["sh", "-c", "pwd; umask; echo \"$VAR1\""].map(String::from),
+        )?;
+        let output = Command::new(&argv[0]).args(&argv[1..]).output()?;
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "/tmp\n0027\ntwo  'words'\n"
+        );
+        Ok(())
+    }
+}
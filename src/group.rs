@@ -0,0 +1,284 @@
+use crate::config::{get_start_order, Program};
+use crate::launcher::Launcher;
+use log::{error, info};
+use std::io::Result;
+use std::thread;
+
+/// Programs managed together
+///
+/// Programs are started after their dependencies are ready
+/// and stopped before the programs they depend on.
+///
+pub struct Group {
+    launchers: Vec<Launcher>,
+    /// Indexes of the direct dependencies of each program
+    dependencies: Vec<Vec<usize>>,
+    /// Indexes of the programs, dependencies first
+    order: Vec<usize>,
+}
+
+impl Group {
+    pub fn new(programs: &[Program]) -> Result<Self> {
+        let dependencies = programs
+            .iter()
+            .map(|program| {
+                let ids = program.get_depends_on();
+                (0..programs.len())
+                    .filter(|index| ids.iter().any(|id| id == programs[*index].get_id()))
+                    .collect()
+            })
+            .collect();
+        Ok(Group {
+            launchers: programs.iter().map(Launcher::new).collect(),
+            dependencies,
+            order: get_start_order(programs)?,
+        })
+    }
+
+    pub fn count(&self) -> usize {
+        self.launchers.len()
+    }
+
+    pub fn get(&self, index: usize) -> &Launcher {
+        &self.launchers[index]
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> &mut Launcher {
+        &mut self.launchers[index]
+    }
+
+    pub fn is_running(&self, index: usize) -> bool {
+        self.launchers[index].is_running()
+    }
+
+    /// Indexes of the program and all its dependencies, in the start order.
+    ///
+    pub fn start_order(&self, index: usize) -> Vec<usize> {
+        self.order
+            .iter()
+            .copied()
+            .filter(|other| *other == index || self.depends_on(index, *other))
+            .collect()
+    }
+
+    /// Start the program after all its dependencies are started and ready.
+    /// No blocking.
+    ///
+    pub fn start_async(&self, index: usize) {
+        self.start_in_order(self.start_order(index));
+    }
+
+    /// Start all programs, each one after its dependencies are ready.
+    /// No blocking.
+    ///
+    pub fn start_all_async(&self) {
+        self.start_in_order(self.order.clone());
+    }
+
+    /// Stop the program after all programs depending on it.
+    /// No blocking.
+    ///
+    pub fn stop_async(&self, index: usize) {
+        let order: Vec<usize> = self
+            .order
+            .iter()
+            .rev()
+            .copied()
+            .filter(|other| *other == index || self.depends_on(*other, index))
+            .collect();
+        let launchers = self.select(&order);
+        thread::spawn(move || stop_in_order(launchers));
+    }
+
+    /// Stop all programs, dependencies last.
+    /// No blocking.
+    ///
+    pub fn stop_all_async(&self) {
+        let launchers = self.select(&self.reverse_order());
+        thread::spawn(move || stop_in_order(launchers));
+    }
+
+    /// Stop all programs, dependencies last.
+    /// Blocks the running thread till the shutdown of all programs.
+    ///
+    pub fn stop_all(&self) -> Result<()> {
+        for mut launcher in self.select(&self.reverse_order()) {
+            if launcher.is_running() {
+                launcher.stop()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the program depends on the other one directly or transitively.
+    ///
+    fn depends_on(&self, index: usize, other: usize) -> bool {
+        let mut pending = self.dependencies[index].clone();
+        let mut visited = Vec::new();
+        while let Some(next) = pending.pop() {
+            if next == other {
+                return true;
+            }
+            if !visited.contains(&next) {
+                visited.push(next);
+                pending.extend(self.dependencies[next].iter());
+            }
+        }
+        false
+    }
+
+    fn reverse_order(&self) -> Vec<usize> {
+        self.order.iter().rev().copied().collect()
+    }
+
+    fn select(&self, order: &[usize]) -> Vec<Launcher> {
+        order
+            .iter()
+            .map(|index| self.launchers[*index].clone())
+            .collect()
+    }
+
+    fn start_in_order(&self, order: Vec<usize>) {
+        let launchers = self.select(&order);
+        thread::spawn(move || {
+            if let Err(e) = start_in_order(launchers) {
+                error!("Failed to start the programs: {}", e);
+            }
+        });
+    }
+}
+
+/// Start the programs one by one, waiting till each one is ready.
+/// The programs already running are only waited for.
+///
+fn start_in_order(mut launchers: Vec<Launcher>) -> Result<()> {
+    let last = launchers.len().saturating_sub(1);
+    for (position, launcher) in launchers.iter_mut().enumerate() {
+        if !launcher.is_running() {
+            launcher.start()?;
+        }
+        if position != last {
+            launcher.await_ready()?;
+            info!("Dependency is ready, starting the next program");
+        }
+    }
+    Ok(())
+}
+
+fn stop_in_order(launchers: Vec<Launcher>) {
+    for mut launcher in launchers {
+        if launcher.is_running() {
+            if let Err(e) = launcher.stop() {
+                error!("Failed to stop the program: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_programs;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn await_condition<F: Fn() -> bool>(predicate: F) {
+        let start_time = Instant::now();
+        while !predicate() {
+            assert!(
+                start_time.elapsed() < TIMEOUT,
+                "Timed out waiting for condition"
+            );
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn start_and_stop_in_order() {
+        let programs = parse_programs(
+            r#"
+          [[program]]
+          id = "api"
+          command = "sleep 60"
+          depends_on = ["tunnel"]
+          [program.stop]
+          signal = "TERM"
+
+          [[program]]
+          id = "tunnel"
+          command = "sh -c 'sleep 0.3; echo Listening; exec sleep 60'"
+          ready_pattern = "Listening"
+          [program.stop]
+          signal = "TERM"
+
+          [[program]]
+          id = "other"
+          command = "sleep 60"
+        "#,
+        )
+        .unwrap();
+        let mut group = Group::new(&programs).unwrap();
+        assert_eq!(group.count(), 3);
+
+        let events: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        for (index, id) in ["api", "tunnel"].iter().enumerate() {
+            let events_clone = Arc::clone(&events);
+            group.get_mut(index).set_start_handler(move |_| {
+                events_clone.lock().unwrap().push(format!("start {}", id));
+            });
+            let events_clone = Arc::clone(&events);
+            group.get_mut(index).set_stop_handler(move |_| {
+                events_clone.lock().unwrap().push(format!("stop {}", id));
+            });
+            let events_clone = Arc::clone(&events);
+            group.get_mut(index).set_ready_handler(move |_| {
+                events_clone.lock().unwrap().push(format!("ready {}", id));
+            });
+        }
+
+        group.start_async(0);
+        await_condition(|| events.lock().unwrap().len() == 3);
+        assert!(group.is_running(0));
+        assert!(group.is_running(1));
+        assert!(!group.is_running(2));
+
+        group.stop_async(1);
+        await_condition(|| !group.is_running(1));
+        assert!(!group.is_running(0));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "start tunnel",
+                "ready tunnel",
+                "start api",
+                "stop api",
+                "stop tunnel"
+            ]
+        );
+    }
+
+    #[test]
+    fn dependency_stopped_before_ready() {
+        let programs = parse_programs(
+            r#"
+          [[program]]
+          id = "api"
+          command = "sleep 60"
+          depends_on = ["tunnel"]
+
+          [[program]]
+          id = "tunnel"
+          command = "true"
+          ready_pattern = "Listening"
+        "#,
+        )
+        .unwrap();
+        let group = Group::new(&programs).unwrap();
+        let launchers = group.select(&group.order);
+        assert!(start_in_order(launchers).is_err());
+        assert!(!group.is_running(0));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::config::Probe;
+use crate::environment::Environment;
+use crate::launcher::{open_exit_pipe, open_pidfd};
+use crate::template::split_command;
+use log::debug;
+use std::io::{BufRead, BufReader, ErrorKind, Result, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::fd::AsRawFd;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+use std::{fmt, io};
+
+/// Result of the health check which changed the program health
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum HealthEvent {
+    /// The check passed after the start or after failures
+    Healthy,
+    /// The configured number of checks in a row failed
+    Unhealthy { reason: String },
+}
+
+impl fmt::Display for HealthEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HealthEvent::Healthy => write!(f, "Program is healthy"),
+            HealthEvent::Unhealthy { reason } => write!(f, "Program is unhealthy: {}", reason),
+        }
+    }
+}
+
+/// Run the probe once, failing with the reason of the problem.
+///
+pub fn check(probe: &Probe, timeout: Duration, env: &Environment) -> Result<()> {
+    match probe {
+        Probe::Tcp { address } => connect(address, timeout).map(|_| ()),
+        Probe::Http { url, expect_status } => check_http(url, *expect_status, timeout),
+        Probe::Command { command } => check_command(command, timeout, env),
+    }
+}
+
+fn connect(address: &str, timeout: Duration) -> Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = address.to_socket_addrs()?.collect();
+    let mut last_error = io::Error::new(ErrorKind::NotFound, "No address to connect");
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(io::Error::new(
+        last_error.kind(),
+        format!("Failed to connect {}: {}", address, last_error),
+    ))
+}
+
+fn check_http(url: &str, expect_status: u16, timeout: Duration) -> Result<()> {
+    let (host, path) = parse_url(url)?;
+    let address = match host.contains(':') {
+        true => host.to_string(),
+        false => format!("{}:80", host),
+    };
+
+    let deadline = Instant::now() + timeout;
+    let mut stream = connect(&address, timeout)?;
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let remaining = remaining.max(Duration::from_millis(1));
+    stream.set_read_timeout(Some(remaining))?;
+    stream.set_write_timeout(Some(remaining))?;
+
+    let request = format!(
+        "GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, host
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    let status = parse_status(&status_line)?;
+    if status != expect_status {
+        let msg = format!("{} responded with {}", url, status);
+        return Err(io::Error::other(msg));
+    }
+    Ok(())
+}
+
+/// Split the `http://` URL into the host with optional port and the path.
+///
+fn parse_url(url: &str) -> Result<(&str, &str)> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Only http:// URL is supported"))?;
+    Ok(match rest.find('/') {
+        Some(index) => (&rest[..index], &rest[index..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Extract the status code from `HTTP/1.1 200 OK`.
+///
+fn parse_status(line: &str) -> Result<u16> {
+    let mut parts = line.split_whitespace();
+    match (parts.next(), parts.next().map(str::parse::<u16>)) {
+        (Some(version), Some(Ok(status))) if version.starts_with("HTTP/") => Ok(status),
+        _ => {
+            let msg = format!("Invalid HTTP response '{}'", line.trim_end());
+            Err(io::Error::new(ErrorKind::InvalidData, msg))
+        }
+    }
+}
+
+fn check_command(command: &str, timeout: Duration, env: &Environment) -> Result<()> {
+    let parts = split_command(command)?;
+    if parts.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Empty command string",
+        ));
+    }
+
+    let mut process = Command::new(&parts[0]);
+    process
+        .args(&parts[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    env.apply(&mut process);
+    let mut child = process.spawn()?;
+
+    let status = match wait_timeout(&mut child, timeout) {
+        Ok(Some(status)) => status,
+        result => {
+            debug!("Killing the health check command {}", child.id());
+            let _ = child.kill();
+            let _ = child.wait();
+            result?;
+            let msg = format!("'{}' timed out", command);
+            return Err(io::Error::new(ErrorKind::TimedOut, msg));
+        }
+    };
+    match status.success() {
+        true => Ok(()),
+        false => {
+            let msg = format!("'{}' exited with status {}", command, status);
+            Err(io::Error::other(msg))
+        }
+    }
+}
+
+/// Wait till the child exits or the timeout expires.
+///
+fn wait_timeout(child: &mut Child, timeout: Duration) -> Result<Option<ExitStatus>> {
+    let exit = open_pidfd(child.id()).or_else(|_| open_exit_pipe(child.id()))?;
+    let mut fds = [libc::pollfd {
+        fd: exit.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let deadline = Instant::now() + timeout;
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let millis = remaining.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, millis) } {
+            0 => return Ok(None),
+            n if n > 0 => return child.try_wait(),
+            _ => {
+                let e = io::Error::last_os_error();
+                if e.kind() != ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Serve a single HTTP request with the given status line.
+    ///
+    fn serve_once(status_line: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(format!("{}\r\n\r\n", status_line).as_bytes());
+        });
+        address
+    }
+
+    #[test]
+    fn check_tcp() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let probe = Probe::Tcp { address };
+        assert!(check(&probe, TIMEOUT, &Environment::default()).is_ok());
+
+        drop(listener);
+        assert!(check(&probe, TIMEOUT, &Environment::default()).is_err());
+    }
+
+    #[test]
+    fn check_http_status() {
+        let address = serve_once("HTTP/1.1 200 OK");
+        let probe = Probe::Http {
+            url: format!("http://{}/health", address),
+            expect_status: 200,
+        };
+        assert!(check(&probe, TIMEOUT, &Environment::default()).is_ok());
+
+        let address = serve_once("HTTP/1.1 503 Service Unavailable");
+        let probe = Probe::Http {
+            url: format!("http://{}", address),
+            expect_status: 200,
+        };
+        let res = check(&probe, TIMEOUT, &Environment::default());
+        assert!(res
+            .err()
+            .unwrap()
+            .to_string()
+            .ends_with("responded with 503"));
+    }
+
+    #[test]
+    fn parse_http() {
+        assert_eq!(
+            parse_url("http://localhost:8080/a/b").unwrap(),
+            ("localhost:8080", "/a/b")
+        );
+        assert_eq!(parse_url("http://localhost").unwrap(), ("localhost", "/"));
+        assert!(parse_url("https://localhost").is_err());
+        assert_eq!(parse_status("HTTP/1.0 404 Not Found\r\n").unwrap(), 404);
+        assert!(parse_status("garbage").is_err());
+    }
+
+    #[test]
+    fn check_commands() {
+        let env = Environment::with_vars(HashMap::from([("CODE".to_string(), "3".to_string())]));
+        let probe = Probe::Command {
+            command: "true".to_string(),
+        };
+        assert!(check(&probe, TIMEOUT, &env).is_ok());
+
+        let probe = Probe::Command {
+            command: "sh -c 'exit $CODE'".to_string(),
+        };
+        let res = check(&probe, TIMEOUT, &env);
+        assert!(res.err().unwrap().to_string().contains("exit status: 3"));
+
+        let probe = Probe::Command {
+            command: "sleep 10".to_string(),
+        };
+        let start = Instant::now();
+        let res = check(&probe, Duration::from_millis(200), &env);
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() < TIMEOUT);
+    }
+}
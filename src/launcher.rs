@@ -1,55 +1,412 @@
-use crate::config::Program;
-use log::{debug, error, info, trace};
-use shlex::split;
+use crate::account::Account;
+use crate::cgroup::{ControlGroup, Placement};
+use crate::config::{
+    Cgroup, CommandLine, Health, Hook, Limits, Program, Prompt, Restart, Secret, Signal, Stop,
+};
+use crate::environment::Environment;
+use crate::escalation::Session;
+use crate::health::{self, HealthEvent};
+use crate::limits;
+use crate::logfile::LogFile;
+use crate::output::{LineBuffer, OutputLine, Stream};
+use crate::procfs;
+use crate::pty;
+use crate::secret;
+use crate::stats::{Sampler, Stats};
+use crate::template::{replace_args, split_command, Quoting};
+use log::{debug, error, info, trace, warn};
+use regex::Regex;
 use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
 use std::io::{ErrorKind, Read, Result, Write};
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
 use std::process::{Child, Command, ExitStatus, Stdio};
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime};
 use std::{io, thread};
 
-/// Authorise as superuser using UI
-const SUDO_COMMAND: &str = "pkexec";
+/// Source of the lifecycle events in the log file
+const LOG_LAUNCHER: &str = "launcher";
 
-const READER_STDOUT: &str = "stdout";
-const READER_STDERR: &str = "stderr";
+/// How often the processes left after the program exit are checked while stopping
+const GROUP_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often the resource usage of the running program is sampled
+const STATS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long the input waits for the program to read it before it is rejected
+const INPUT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Output stream of the running program
+///
+struct OutputStream {
+    stream: Stream,
+    hook: Option<Hook>, // the output of the hook command, not of the program
+    file: File,
+    lines: LineBuffer,
+}
+
+/// Progress of the automatic restart of the exited program
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum RestartEvent {
+    /// The program will be started again after the delay
+    Scheduled {
+        attempt: u32,
+        max_retries: u32,
+        delay: Duration,
+    },
+    /// The program was started again
+    Restarted { attempt: u32, max_retries: u32 },
+    /// The scheduled restart was cancelled by stop request
+    Cancelled,
+    /// All retries are exhausted, the program stays stopped
+    GaveUp { retries: u32 },
+}
+
+impl fmt::Display for RestartEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartEvent::Scheduled {
+                attempt,
+                max_retries,
+                delay,
+            } => write!(
+                f,
+                "Restarting in {:.1}s (attempt {}/{})",
+                delay.as_secs_f64(),
+                attempt,
+                max_retries
+            ),
+            RestartEvent::Restarted { .. } => write!(f, "Program restarted"),
+            RestartEvent::Cancelled => write!(f, "Restart cancelled"),
+            RestartEvent::GaveUp { retries } => write!(f, "Gave up after {} retries", retries),
+        }
+    }
+}
+
+/// Progress of the program stop sequence
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopEvent {
+    /// The stop signal was sent to the program
+    Signalled(Signal),
+    /// The program outlived the grace timeout, so a stronger signal was sent
+    Escalated { signal: Signal, timeout: Duration },
+}
+
+impl fmt::Display for StopEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopEvent::Signalled(signal) => write!(f, "Stopping the program with {}", signal),
+            StopEvent::Escalated { signal, timeout } => write!(
+                f,
+                "Program is still running after {:.1}s, sending {}",
+                timeout.as_secs_f64(),
+                signal
+            ),
+        }
+    }
+}
+
+/// Readiness of the started program, detected by its output
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReadyEvent {
+    /// The program printed the line matching the ready pattern
+    Ready { elapsed: Duration },
+    /// The ready line did not appear in time, so the start is failed
+    TimedOut { timeout: Duration },
+}
+
+impl fmt::Display for ReadyEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReadyEvent::Ready { elapsed } => {
+                write!(f, "Program is ready after {:.1}s", elapsed.as_secs_f64())
+            }
+            ReadyEvent::TimedOut { timeout } => write!(
+                f,
+                "Program is not ready after {:.1}s, stopping it",
+                timeout.as_secs_f64()
+            ),
+        }
+    }
+}
+
+/// Progress of the hook commands run around the program start and stop
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum HookEvent {
+    /// The hook command was started
+    Started { hook: Hook, command: String },
+    /// The hook command could not be started or exited with an error
+    Failed {
+        hook: Hook,
+        command: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for HookEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookEvent::Started { hook, command } => write!(f, "Running {}: {}", hook, command),
+            HookEvent::Failed {
+                hook,
+                command,
+                reason,
+            } => write!(f, "{} '{}' failed: {}", hook, command, reason),
+        }
+    }
+}
 
 /// Launch any CLI-program
 ///
+/// Clones share the same running program.
+///
+#[derive(Clone)]
 pub struct Launcher {
-    command: String,
-    superuser: bool,
+    id: String,
+    command: CommandLine,
+    shell: Option<String>,
+    /// Command line prefix running the program as superuser
+    escalation: Option<Vec<String>>,
+    /// Account to run the program as
+    account: Option<Account>,
+    pty: bool,
     input: Option<String>,
-    env: HashMap<String, String>,
-    child: Arc<Mutex<Option<Child>>>,
-    output_handler: Arc<Mutex<dyn FnMut(String) + Send>>,
+    /// Args resolved at the start, their placeholders are left in the command, input and hooks
+    secrets: Vec<(String, Secret)>,
+    /// Args entered by the user before the start, substituted as the secret ones
+    prompts: Vec<Prompt>,
+    env: Environment,
+    max_line_length: usize,
+    ready_pattern: Option<Regex>,
+    ready_timeout: Option<Duration>,
+    restart: Restart,
+    stop: Stop,
+    limits: Limits,
+    /// Control group to run the program in
+    cgroup: Option<Cgroup>,
+    health: Option<Health>,
+    hooks: HashMap<Hook, Vec<String>>,
+    log: Option<Arc<Mutex<LogFile>>>,
+    shared: Arc<Shared>,
+    output_handler: Arc<Mutex<dyn FnMut(OutputLine) + Send>>,
+    start_handler: Arc<Mutex<dyn FnMut(u32) + Send>>,
     status_handler: Arc<Mutex<dyn FnMut(ExitStatus) + Send>>,
+    restart_handler: Arc<Mutex<dyn FnMut(RestartEvent) + Send>>,
+    stop_handler: Arc<Mutex<dyn FnMut(StopEvent) + Send>>,
+    health_handler: Arc<Mutex<dyn FnMut(HealthEvent) + Send>>,
+    ready_handler: Arc<Mutex<dyn FnMut(ReadyEvent) + Send>>,
+    hook_handler: Arc<Mutex<dyn FnMut(HookEvent) + Send>>,
+    stats_handler: Arc<Mutex<dyn FnMut(Stats) + Send>>,
+}
+
+/// State shared between the launcher and its background threads
+///
+#[derive(Default)]
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    child: Option<Child>,
+    /// The exited program is waiting for the restart
+    restart_pending: bool,
+    /// Stop was requested, so the exited program must not be restarted
+    stop_requested: bool,
+    /// Number of restarts since the program was started manually
+    retries: u32,
+    /// When the running program was started
+    started_at: Option<Instant>,
+    /// The running program printed the ready line
+    ready: bool,
+    /// The last health check of the running program passed
+    healthy: bool,
+    /// The running program is being stopped as failed: not ready in time or unhealthy
+    failed: bool,
+    /// The exited program is cleaned up by the post_stop hook
+    cleanup_pending: bool,
+    /// The program is being spawned, without the lock held
+    spawning: bool,
+    /// Values of the prompted args for the next start
+    prompted: HashMap<String, String>,
+    /// Values of the secret and prompted args of the running program
+    args: HashMap<String, String>,
+    /// Values of the args hidden in the logs: secrets and hidden prompted args
+    masked: Vec<String>,
+    /// Master side of the pseudo-terminal of the running program
+    pty: Option<File>,
+    /// Input of the running program: the stdin pipe or the pseudo-terminal, till the end
+    stdin: Option<Arc<Input>>,
+    /// Elevated session of the running superuser program, serving its stop signals
+    session: Option<Arc<Session>>,
+    /// Control group of the last started program, kept till the next start,
+    /// since its descendants may outlive the program
+    cgroup: Option<Arc<ControlGroup>>,
+    /// Size of the terminal window in characters, if it is known
+    window_size: Option<(u16, u16)>,
+}
+
+impl State {
+    fn is_active(&self) -> bool {
+        self.child.is_some() || self.restart_pending || self.cleanup_pending || self.spawning
+    }
+
+    fn is_running(&self, pid: u32) -> bool {
+        self.child.as_ref().map(|child| child.id()) == Some(pid)
+    }
+}
+
+/// The spawned program with everything serving it, not published in the state yet
+///
+struct Spawned {
+    child: Child,
+    master: Option<File>,
+    stdin: Arc<Input>,
+    session: Option<Session>,
+    cgroup: Option<ControlGroup>,
+    streams: Vec<OutputStream>,
+    exit: OwnedFd,
+}
+
+/// Input of the running program, written without blocking the caller.
+/// The rest of the text the program doesn't take in time is written in background,
+/// so the lines are never cut.
+///
+struct Input {
+    file: File,
+    /// The rest of the previous text is being written in background
+    busy: AtomicBool,
+}
+
+impl Input {
+    fn new(file: File) -> Self {
+        setup_unblocking(&file);
+        Input {
+            file,
+            busy: AtomicBool::new(false),
+        }
+    }
+
+    /// Write the text, waiting up to the timeout for the program to read its beginning.
+    /// The text is rejected only if the program takes nothing of it.
+    ///
+    fn write(self: &Arc<Self>, text: &[u8], timeout: Duration) -> Result<()> {
+        if self.busy.swap(true, Ordering::AcqRel) {
+            return Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "Previous input is still being written",
+            ));
+        }
+        let written = match self.write_some(text, Some(timeout)) {
+            Ok(0) if !text.is_empty() => Err(io::Error::new(
+                ErrorKind::WouldBlock,
+                "Program doesn't read its input",
+            )),
+            result => result,
+        };
+        match written {
+            Ok(written) if written < text.len() => {
+                let input = Arc::clone(self);
+                let rest = text[written..].to_vec();
+                thread::spawn(move || {
+                    if let Err(e) = input.write_some(&rest, None) {
+                        warn!("Failed to write the input: {}", e);
+                    }
+                    input.busy.store(false, Ordering::Release);
+                });
+                Ok(())
+            }
+            result => {
+                self.busy.store(false, Ordering::Release);
+                result.map(|_| ())
+            }
+        }
+    }
+
+    /// Write as much of the text as the program reads, till it stops reading for the timeout.
+    /// Returns the number of the written bytes.
+    ///
+    fn write_some(&self, text: &[u8], timeout: Option<Duration>) -> Result<usize> {
+        let mut written = 0;
+        while written < text.len() {
+            match (&self.file).write(&text[written..]) {
+                Ok(count) => written += count,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if !poll_writable(&self.file, timeout)? {
+                        break;
+                    }
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(written)
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
 }
 
 impl Launcher {
     pub fn new(program: &Program) -> Self {
         Launcher {
-            command: program.get_command().clone(),
-            superuser: program.need_superuser(),
-            input: program.get_input().clone(),
-            env: program.get_env().clone(),
-            child: Arc::new(Mutex::new(None)),
+            id: program.get_id().to_string(),
+            command: program.get_command(),
+            shell: program.get_shell(),
+            escalation: program.get_escalation(),
+            account: program.get_account().cloned(),
+            pty: program.need_pty(),
+            input: program.get_input(),
+            secrets: program.get_secrets(),
+            prompts: program.get_prompts(),
+            env: Environment::new(program),
+            max_line_length: program.get_max_line_length(),
+            ready_pattern: program.get_ready_pattern(),
+            ready_timeout: program.get_ready_timeout(),
+            restart: program.get_restart().clone(),
+            stop: program.get_stop().clone(),
+            limits: program.get_limits().clone(),
+            cgroup: program.get_cgroup().cloned(),
+            health: program.get_health(),
+            hooks: Hook::ALL
+                .iter()
+                .map(|hook| (*hook, program.get_hook_commands(*hook)))
+                .collect(),
+            log: program
+                .get_log()
+                .map(|log| Arc::new(Mutex::new(LogFile::new(log, program.get_id())))),
+            shared: Arc::new(Shared::default()),
             output_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
+            start_handler: Arc::new(Mutex::new(|_| {})),  // default empty handler
             status_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
+            restart_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
+            stop_handler: Arc::new(Mutex::new(|_| {})),   // default empty handler
+            health_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
+            ready_handler: Arc::new(Mutex::new(|_| {})),  // default empty handler
+            hook_handler: Arc::new(Mutex::new(|_| {})),   // default empty handler
+            stats_handler: Arc::new(Mutex::new(|_| {})),  // default empty handler
         }
     }
 
     #[cfg(test)]
     fn test_new(command: String, env: HashMap<String, String>) -> Self {
+        let program = crate::config::parse_content("id = 'test'\ncommand = ''").unwrap();
         Launcher {
-            command: command.clone(),
-            superuser: false,
-            input: None,
-            env: env.clone(),
-            child: Arc::new(Mutex::new(None)),
-            output_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
-            status_handler: Arc::new(Mutex::new(|_| {})), // default empty handler
+            command: CommandLine::Line(command),
+            env: Environment::with_vars(env),
+            ..Self::new(&program)
         }
     }
 
@@ -57,11 +414,20 @@ impl Launcher {
     ///
     pub fn set_output_handler<F>(&mut self, handler: F)
     where
-        F: FnMut(String) + Send + 'static,
+        F: FnMut(OutputLine) + Send + 'static,
     {
         self.output_handler = Arc::new(Mutex::new(handler));
     }
 
+    /// Setup program started event handler, receiving the pid of the program
+    ///
+    pub fn set_start_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(u32) + Send + 'static,
+    {
+        self.start_handler = Arc::new(Mutex::new(handler));
+    }
+
     /// Setup program stopped event handler
     ///
     pub fn set_status_handler<F>(&mut self, handler: F)
@@ -71,15 +437,237 @@ impl Launcher {
         self.status_handler = Arc::new(Mutex::new(handler));
     }
 
-    /// Start program
+    /// Setup automatic restart events handler
+    ///
+    pub fn set_restart_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(RestartEvent) + Send + 'static,
+    {
+        self.restart_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Setup program stop sequence events handler
+    ///
+    pub fn set_stop_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(StopEvent) + Send + 'static,
+    {
+        self.stop_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Setup health check events handler
+    ///
+    pub fn set_health_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(HealthEvent) + Send + 'static,
+    {
+        self.health_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Setup program readiness events handler
+    ///
+    pub fn set_ready_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(ReadyEvent) + Send + 'static,
+    {
+        self.ready_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Setup hook commands events handler
+    ///
+    pub fn set_hook_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(HookEvent) + Send + 'static,
+    {
+        self.hook_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Setup resource usage handler, sampled every second while the program runs
+    ///
+    pub fn set_stats_handler<F>(&mut self, handler: F)
+    where
+        F: FnMut(Stats) + Send + 'static,
+    {
+        self.stats_handler = Arc::new(Mutex::new(handler));
+    }
+
+    /// Start program after its pre_start hook commands,
+    /// failing without the start if any of them fails.
     ///
     pub fn start(&mut self) -> Result<()> {
-        if is_running(&self.child) {
-            return Err(io::Error::new(ErrorKind::Other, "Already started"));
+        // checked before the hooks too, so they don't run for the running program
+        if self.is_running() {
+            return Err(io::Error::other("Already started"));
+        }
+        self.resolve_args()?;
+        self.run_hooks(Hook::PreStart)?;
+
+        let mut state = self.shared.lock();
+        if state.is_active() {
+            return Err(io::Error::other("Already started"));
+        }
+
+        state.retries = 0;
+        state.stop_requested = false;
+        let (state, result) = self.spawn(state);
+        result?;
+        let pid = state.child.as_ref().map_or(0, |child| child.id());
+        drop(state);
+
+        let mut handler = self.start_handler.lock().unwrap();
+        (handler)(pid);
+        drop(handler);
+        self.run_post_start();
+        Ok(())
+    }
+
+    /// Set the size of the terminal window in characters,
+    /// which is the size of the pseudo-terminal of the program.
+    ///
+    pub fn set_window_size(&self, columns: u16, rows: u16) {
+        let mut state = self.shared.lock();
+        state.window_size = Some((columns, rows));
+        if let Some(master) = state.pty.as_ref() {
+            if let Err(e) = pty::set_window_size(master, (columns, rows)) {
+                warn!("Failed to resize the terminal: {}", e);
+            }
+        }
+    }
+
+    /// Write the text to the input of the running program.
+    /// Waits shortly for the program to read, the text is rejected if it reads nothing.
+    ///
+    pub fn write_input(&self, text: &str) -> Result<()> {
+        self.get_stdin()?.write(text.as_bytes(), INPUT_TIMEOUT)
+    }
+
+    /// End the input of the running program: close the stdin pipe
+    /// or type the end-of-file character on the terminal.
+    ///
+    pub fn send_eof(&self) -> Result<()> {
+        let stdin = self.get_stdin()?;
+        let mut state = self.shared.lock();
+        match state.pty.as_ref() {
+            Some(master) => stdin.write(&[pty::eof_char(master)?], INPUT_TIMEOUT),
+            None => {
+                state.stdin = None;
+                Ok(())
+            }
         }
+    }
+
+    fn get_stdin(&self) -> Result<Arc<Input>> {
+        let state = self.shared.lock();
+        match (state.child.as_ref(), state.stdin.as_ref()) {
+            (Some(_), Some(stdin)) => Ok(Arc::clone(stdin)),
+            (Some(_), None) => Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "Input of the program is closed",
+            )),
+            (None, _) => Err(io::Error::new(
+                ErrorKind::NotConnected,
+                "Program is not running",
+            )),
+        }
+    }
+
+    /// Args to be entered by the user before every start.
+    ///
+    pub fn get_prompts(&self) -> &[Prompt] {
+        &self.prompts
+    }
+
+    /// Set the values of the prompted args for the next start.
+    ///
+    pub fn set_prompted_args(&self, values: HashMap<String, String>) {
+        self.shared.lock().prompted = values;
+    }
+
+    /// Resolve the secret args and take the prompted ones for the run,
+    /// failing if any prompted arg was not entered.
+    ///
+    fn resolve_args(&self) -> Result<()> {
+        let mut args = secret::resolve_all(&self.secrets)?;
+        let mut masked: Vec<String> = args.values().cloned().collect();
+        let mut prompted = std::mem::take(&mut self.shared.lock().prompted);
+        for prompt in self.prompts.iter() {
+            let Some(value) = prompted.remove(prompt.get_name()) else {
+                let msg = format!(
+                    "Arg '{}' must be entered before the start",
+                    prompt.get_name()
+                );
+                return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+            };
+            if prompt.is_hidden() {
+                masked.push(value.clone());
+            }
+            args.insert(prompt.get_name().to_string(), value);
+        }
+
+        let mut state = self.shared.lock();
+        state.args = args;
+        state.masked = masked;
+        Ok(())
+    }
+
+    /// Wait till the started program is ready to serve the programs depending on it:
+    /// it printed the ready line and passed the health check, if they are configured.
+    /// Fails if the program is stopped before.
+    ///
+    pub fn await_ready(&self) -> Result<()> {
+        let state = self.shared.lock();
+        let state = self
+            .shared
+            .changed
+            .wait_while(state, |state| {
+                state.is_active() && !state.stop_requested && !self.is_ready(state)
+            })
+            .unwrap();
+        match self.is_ready(&state) {
+            true => Ok(()),
+            false => Err(io::Error::new(
+                ErrorKind::Interrupted,
+                "Program stopped before it became ready",
+            )),
+        }
+    }
+
+    fn is_ready(&self, state: &State) -> bool {
+        state.child.is_some()
+            && (self.ready_pattern.is_none() || state.ready)
+            && (self.health.is_none() || state.healthy)
+    }
 
-        // Parse the command string into program and arguments
-        let parts = split(&self.command).unwrap_or_else(|| vec![self.command.to_string()]);
+    /// Spawn the program and the threads serving it.
+    /// The lock is released while the program is spawned, since placing it into its cgroup
+    /// may wait for systemd, the state stays active meanwhile.
+    ///
+    fn spawn<'a>(
+        &'a self,
+        mut state: MutexGuard<'a, State>,
+    ) -> (MutexGuard<'a, State>, Result<()>) {
+        state.spawning = true;
+        let (args, window_size) = (state.args.clone(), state.window_size);
+        drop(state);
+
+        let spawned = self.launch(&args, window_size);
+
+        let mut state = self.shared.lock();
+        state.spawning = false;
+        let result = spawned.map(|spawned| self.serve(&mut state, spawned));
+        self.shared.changed.notify_all();
+        (state, result)
+    }
+
+    /// Spawn the program with the values of its args, placed into its cgroup and given its input.
+    ///
+    fn launch(
+        &self,
+        values: &HashMap<String, String>,
+        window_size: Option<(u16, u16)>,
+    ) -> Result<Spawned> {
+        // Parse the command into program and arguments
+        let parts = self.command.to_argv(values, self.shell.as_deref())?;
         if parts.is_empty() {
             return Err(io::Error::new(
                 ErrorKind::InvalidInput,
@@ -87,226 +675,1061 @@ impl Launcher {
             ));
         }
 
+        // The escalated program switches to the account from the superuser one
+        let (parts, session) = match (self.escalation.as_ref(), self.account.as_ref()) {
+            (Some(escalation), account) => {
+                let parts = account.map_or(parts.clone(), |account| account.wrap(&parts));
+                let session = Session::open()?;
+                (session.wrap(escalation, &self.env, &parts)?, Some(session))
+            }
+            (None, _) => (parts, None),
+        };
+
         // Extract the program name and arguments
-        let (program, args) = match self.superuser {
-            true => (&SUDO_COMMAND.to_string(), &parts[..]),
-            false => (&parts[0], &parts[1..]),
+        let (program, args) = (&parts[0], &parts[1..]);
+
+        let pty = match self.pty {
+            true => Some(pty::open(window_size.unwrap_or(pty::DEFAULT_SIZE))?),
+            false => None,
         };
 
-        let mut child = Command::new(program)
-            .args(args)
-            .stdout(Stdio::piped()) // Capture stdout
-            .stderr(Stdio::piped()) // Capture stderr
-            .stdin(Stdio::piped())
-            .envs(self.env.iter()) // Add environment variables from the HashMap
-            .spawn()?;
-
-        if self.input.is_some() {
-            if let Some(mut stdin) = child.stdin.take() {
-                let input = self.input.as_ref().unwrap();
-                stdin
-                    .write_all(input.as_bytes())
-                    .expect("Failed to write to stdin");
+        let mut command = Command::new(program);
+        command.args(args);
+        match pty.as_ref() {
+            Some(pty) => command
+                .stdout(pty.slave.try_clone()?)
+                .stderr(pty.slave.try_clone()?)
+                .stdin(pty.slave.try_clone()?),
+            None => command
+                .stdout(Stdio::piped()) // Capture stdout
+                .stderr(Stdio::piped()) // Capture stderr
+                .stdin(Stdio::piped()),
+        };
+        // the program is moved into the group first, while it has the rights of the tray
+        let placement = self
+            .cgroup
+            .as_ref()
+            .map(|cgroup| Placement::prepare(cgroup, &self.id, &mut command))
+            .transpose()?;
+        // the limits are set before the account switch, which may drop the rights to raise them
+        limits::apply(&self.limits, &mut command);
+        // the escalated program gets its environment from the helper,
+        // while the escalation command keeps the one of the tray
+        match (self.escalation.as_ref(), self.account.as_ref()) {
+            // the variables of the account are set after `clear_env` drops the inherited ones
+            (None, Some(account)) => {
+                self.env.apply(&mut command);
+                account.apply(&mut command);
             }
+            (None, None) => self.env.apply(&mut command),
+            (Some(_), _) => {}
         }
 
-        let mut stdout = child.stdout.take().expect("Failed to get stdout");
-        setup_unblocking(&stdout);
-        let mut stderr = child.stderr.take().expect("Failed to get stderr");
-        setup_unblocking(&stderr);
+        // Run the program in its own session, so its process group id is the program pid
+        // and the stop signals reach every process it forks
+        let controlling_terminal = pty.is_some();
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                // the pseudo-terminal on stdin becomes the terminal of the new session
+                if controlling_terminal && libc::ioctl(0, libc::TIOCSCTTY, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
 
-        info!("Starting the program loop {:?}", child);
-        keep_child(&self.child, child);
+        let input = self
+            .input
+            .as_ref()
+            .map(|input| replace_args(input, &[values], Quoting::Plain))
+            .transpose()?;
+        let spawned = command.spawn();
+        // the program keeps the only slave side, so the master one is hung up after its exit
+        drop(command);
+        let (mut child, cgroup) = match (spawned, placement.map(Placement::finish).transpose()) {
+            (Ok(child), Ok(cgroup)) => (child, cgroup),
+            // the failed placement is the reason of the failed spawn
+            (_, Err(e)) => {
+                let msg = format!("Failed to place the program into its cgroup: {}", e);
+                return Err(io::Error::new(e.kind(), msg));
+            }
+            (Err(e), Ok(_)) => return Err(e),
+        };
+        let master = pty.map(|pty| pty.master);
+
+        let stdin = match master.as_ref() {
+            Some(master) => master.try_clone()?,
+            None => {
+                let stdin = child.stdin.take().expect("Failed to get stdin");
+                File::from(OwnedFd::from(stdin))
+            }
+        };
+        let stdin = Arc::new(Input::new(stdin));
+
+        let streams = match master.as_ref() {
+            Some(master) => vec![self.take_terminal_output(master)?],
+            None => self.take_output(&mut child, None),
+        };
+
+        let exit = match open_pidfd(child.id()).or_else(|e| {
+            debug!("No pidfd support ({}), waiting for exit in a thread", e);
+            open_exit_pipe(child.id())
+        }) {
+            Ok(exit) => exit,
+            Err(e) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
+            }
+        };
+        // never waits under the lock: the rest the pipe doesn't hold is written in background,
+        // once the program reads it while its output is served
+        if let Some(Err(e)) = input.map(|input| stdin.write(input.as_bytes(), Duration::ZERO)) {
+            let _ = child.kill();
+            let _ = child.wait();
+            let msg = format!("Failed to write the input: {}", e);
+            return Err(io::Error::new(e.kind(), msg));
+        }
+
+        Ok(Spawned {
+            child,
+            master,
+            stdin,
+            session,
+            cgroup,
+            streams,
+            exit,
+        })
+    }
 
-        let output_handler = Arc::clone(&self.output_handler);
-        let child = Arc::clone(&self.child);
-        thread::spawn(move || process_output(READER_STDOUT, &mut stdout, &child, output_handler));
+    /// Publish the spawned program in the state and start the threads serving it.
+    /// The program is stopped right away if the stop was requested while it was spawned.
+    ///
+    fn serve(&self, state: &mut State, spawned: Spawned) {
+        let Spawned {
+            child,
+            master,
+            stdin,
+            session,
+            cgroup,
+            streams,
+            exit,
+        } = spawned;
+        info!("Starting the program loop {:?}", child);
+        let pid = child.id();
+        self.write_log(
+            SystemTime::now(),
+            LOG_LAUNCHER,
+            &format!("Started with pid {}", pid),
+        );
+        if !self.limits.is_empty() {
+            self.write_log(
+                SystemTime::now(),
+                LOG_LAUNCHER,
+                &format!("Limits: {}", self.limits),
+            );
+        }
+        state.cgroup = cgroup.map(Arc::new);
+        if let Some(cgroup) = state.cgroup.as_ref() {
+            self.write_log(
+                SystemTime::now(),
+                LOG_LAUNCHER,
+                &format!("Cgroup: {}", cgroup.get_dir().display()),
+            );
+        }
+        state.child = Some(child);
+        state.pty = master;
+        state.stdin = Some(stdin);
+        state.session = session.map(Arc::new);
+        state.restart_pending = false;
+        state.started_at = Some(Instant::now());
+        state.ready = false;
+        state.healthy = false;
+        state.failed = false;
+
+        let launcher = self.clone();
+        thread::spawn(move || process_events(&launcher, streams, exit));
+
+        if let (Some(_), Some(timeout)) = (self.ready_pattern.as_ref(), self.ready_timeout) {
+            let launcher = self.clone();
+            thread::spawn(move || process_ready_timeout(&launcher, timeout, pid));
+        }
 
-        let output_handler = Arc::clone(&self.output_handler);
-        let child = Arc::clone(&self.child);
-        thread::spawn(move || process_output(READER_STDERR, &mut stderr, &child, output_handler));
+        if let Some(health) = self.health.clone() {
+            let launcher = self.clone();
+            thread::spawn(move || process_health(&launcher, &health, pid));
+        }
 
-        let status_handler = Arc::clone(&self.status_handler);
-        let child = Arc::clone(&self.child);
-        thread::spawn(move || process_status(&child, status_handler));
+        let launcher = self.clone();
+        thread::spawn(move || process_stats(&launcher, pid));
 
-        Ok(())
+        if state.stop_requested {
+            debug!("Stopping the program started meanwhile");
+            let launcher = self.clone();
+            thread::spawn(move || {
+                if let Err(e) = terminate(&launcher, pid) {
+                    error!("Failed to stop the program: {}", e);
+                }
+            });
+        }
     }
 
-    /// Stop the running program.
+    /// Stop the running program with the configured signal,
+    /// escalating to `SIGTERM` and then `SIGKILL` after the grace timeout.
     /// Blocks the running thread till the program shutdown.
     ///
     pub fn stop(&mut self) -> Result<()> {
-        stop(&self.child, self.superuser, false)
+        stop(self, false)
     }
 
     /// Stop the running program.
     /// No blocking.
     ///
+    #[cfg(test)]
     pub fn stop_async(&mut self) {
-        let child = Arc::clone(&self.child);
-        let is_superuser = self.superuser;
-        thread::spawn(move || stop(&child, is_superuser, true));
+        let launcher = self.clone();
+        thread::spawn(move || stop(&launcher, true));
     }
 
-    /// Check if the program still running.
+    /// Check if the program still running or waiting for the restart.
     ///
     pub fn is_running(&self) -> bool {
-        is_running(&self.child)
+        self.shared.lock().is_active()
     }
-}
 
-fn setup_unblocking(output: &dyn AsRawFd) {
-    let fd = output.as_raw_fd();
-    unsafe {
-        let flags = libc::fcntl(output.as_raw_fd(), libc::F_GETFL, 0);
-        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    /// Take the output streams of the spawned program or hook command.
+    ///
+    fn take_output(&self, child: &mut Child, hook: Option<Hook>) -> Vec<OutputStream> {
+        let stdout = child.stdout.take().expect("Failed to get stdout");
+        setup_unblocking(&stdout);
+        let stderr = child.stderr.take().expect("Failed to get stderr");
+        setup_unblocking(&stderr);
+        vec![
+            OutputStream {
+                stream: Stream::Stdout,
+                hook,
+                file: File::from(OwnedFd::from(stdout)),
+                lines: LineBuffer::new(self.max_line_length),
+            },
+            OutputStream {
+                stream: Stream::Stderr,
+                hook,
+                file: File::from(OwnedFd::from(stderr)),
+                lines: LineBuffer::new(self.max_line_length),
+            },
+        ]
     }
-}
 
-fn process_output(
-    reader_name: &str,
-    reader: &mut dyn Read,
-    child: &Arc<Mutex<Option<Child>>>,
-    output_handler: Arc<Mutex<dyn FnMut(String) + Send>>,
-) {
-    let mut buf = [0u8; 1024];
-    loop {
-        match reader.read(&mut buf) {
-            Ok(0) => {
-                trace!("Read {} bytes from {}", buf.len(), reader_name);
-            }
-            Ok(n) => {
-                let str = String::from_utf8_lossy(&buf[..n]);
-                trace!("{}: {}", reader_name, str);
-                let mut handler = output_handler.lock().unwrap();
-                (handler)(str.to_string());
-                continue;
-            }
-            Err(e) if e.kind() == ErrorKind::WouldBlock => {
-                thread::sleep(Duration::from_millis(100));
-                continue;
+    /// Take the output of the program running on the pseudo-terminal,
+    /// where stdout and stderr are the same stream.
+    ///
+    fn take_terminal_output(&self, master: &File) -> Result<OutputStream> {
+        let file = master.try_clone()?;
+        setup_unblocking(&file);
+        Ok(OutputStream {
+            stream: Stream::Stdout,
+            hook: None,
+            file,
+            lines: LineBuffer::new(self.max_line_length),
+        })
+    }
+
+    /// Run the commands of the hook one by one, stopping at the first failed one.
+    ///
+    fn run_hooks(&self, hook: Hook) -> Result<()> {
+        for command in self.hooks.get(&hook).into_iter().flatten() {
+            self.notify_hook(HookEvent::Started {
+                hook,
+                command: command.clone(),
+            });
+            if let Err(e) = self.run_hook(hook, command) {
+                error!("Hook {} '{}' failed: {}", hook, command, e);
+                self.notify_hook(HookEvent::Failed {
+                    hook,
+                    command: command.clone(),
+                    reason: e.to_string(),
+                });
+                let msg = format!("{} '{}' failed: {}", hook, command, e);
+                return Err(io::Error::new(e.kind(), msg));
             }
-            Err(e) => {
-                error!("Error occurred while reading {}: {}", reader_name, e);
+        }
+        Ok(())
+    }
+
+    /// Run the hook command with the program environment till it exits,
+    /// passing its output to the handler.
+    ///
+    fn run_hook(&self, hook: Hook, command: &str) -> Result<()> {
+        let command = replace_args(command, &[&self.shared.lock().args], Quoting::Shell)?;
+        let parts = split_command(&command)?;
+        if parts.is_empty() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Empty command string",
+            ));
+        }
+
+        let mut command = Command::new(&parts[0]);
+        command
+            .args(&parts[1..])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        self.env.apply(&mut command);
+        let mut child = command.spawn()?;
+
+        let mut streams = self.take_output(&mut child, Some(hook));
+        while !streams.is_empty() {
+            let mut fds: Vec<libc::pollfd> = streams.iter().map(|s| pollfd(&s.file)).collect();
+            if let Err(e) = poll(&mut fds) {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(e);
             }
+            let mut ready = fds.iter().map(|fd| fd.revents != 0);
+            streams.retain_mut(|stream| !ready.next().unwrap() || process_output(self, stream));
         }
-        if !is_running(&child) {
-            debug!("Stopping loop {}", reader_name);
-            break;
+
+        let status = child.wait()?;
+        match status.success() {
+            true => Ok(()),
+            false => Err(io::Error::other(format!("exited with {}", status))),
         }
     }
-}
 
-fn process_status(
-    child: &Arc<Mutex<Option<Child>>>,
-    status_handler: Arc<Mutex<dyn FnMut(ExitStatus) + Send>>,
-) {
-    loop {
-        debug!("Check process status...");
-        match wait_child(child) {
-            Ok(Some(status)) => {
-                info!("Program exited with status: {}", status);
-                let mut handler = status_handler.lock().unwrap();
-                (handler)(status);
-                break;
-            }
-            Err(e) => {
-                error!("Error occurred while waiting for the process: {}", e);
-                break;
-            }
-            Ok(None) => {
-                trace!("Program is still running...");
-                thread::sleep(Duration::from_secs(1)); // Wait for 1 second before checking again
+    /// Run the post_start hook commands in background, only reporting their failures.
+    ///
+    fn run_post_start(&self) {
+        if self.hooks[&Hook::PostStart].is_empty() {
+            return;
+        }
+        let launcher = self.clone();
+        thread::spawn(move || launcher.run_hooks(Hook::PostStart));
+    }
+
+    /// Copy the program output or lifecycle event to the log file, if configured.
+    ///
+    fn write_log(&self, time: SystemTime, source: &str, text: &str) {
+        if let Some(log) = self.log.as_ref() {
+            if let Err(e) = log.lock().unwrap().write_line(time, source, text) {
+                error!("Failed to write the log file: {}", e);
             }
         }
     }
-    forget_child(child);
-}
 
-fn keep_child(state: &Arc<Mutex<Option<Child>>>, new_child: Child) {
-    let mut locked = state.lock().unwrap(); // or handle the error properly
-    *locked = Some(new_child);
-}
+    fn notify_restart(&self, event: RestartEvent) {
+        info!("Restart: {:?}", event);
+        self.write_log(SystemTime::now(), LOG_LAUNCHER, &event.to_string());
+        let mut handler = self.restart_handler.lock().unwrap();
+        (handler)(event);
+    }
 
-fn forget_child(state: &Arc<Mutex<Option<Child>>>) {
-    let mut locked = state.lock().unwrap();
-    *locked = None;
-}
+    fn notify_stop(&self, event: StopEvent) {
+        info!("Stop: {:?}", event);
+        self.write_log(SystemTime::now(), LOG_LAUNCHER, &event.to_string());
+        let mut handler = self.stop_handler.lock().unwrap();
+        (handler)(event);
+    }
 
-fn wait_child(state: &Arc<Mutex<Option<Child>>>) -> Result<Option<ExitStatus>> {
-    let mut locked = state.lock().unwrap();
-    if let Some(child) = locked.as_mut() {
-        child.try_wait()
-    } else {
-        Err(io::Error::new(
-            io::ErrorKind::NotFound,
-            "No child is running",
-        ))
+    /// Report the readiness on the first output line matching the ready pattern.
+    ///
+    fn mark_ready(&self) {
+        let mut state = self.shared.lock();
+        if state.ready || state.child.is_none() {
+            return;
+        }
+        state.ready = true;
+        self.shared.changed.notify_all();
+        let elapsed = state
+            .started_at
+            .map_or(Duration::ZERO, |time| time.elapsed());
+        drop(state);
+        self.notify_ready(ReadyEvent::Ready { elapsed });
     }
-}
 
-fn is_running(state: &Arc<Mutex<Option<Child>>>) -> bool {
-    let locked = state.lock().unwrap();
-    match locked.as_ref() {
-        None => false,
-        Some(_) => true,
+    fn notify_ready(&self, event: ReadyEvent) {
+        info!("Ready: {:?}", event);
+        self.write_log(SystemTime::now(), LOG_LAUNCHER, &event.to_string());
+        let mut handler = self.ready_handler.lock().unwrap();
+        (handler)(event);
     }
-}
 
-pub fn stop(state: &Arc<Mutex<Option<Child>>>, is_superuser: bool, is_async: bool) -> Result<()> {
-    if !is_running(state) {
-        debug!("Already stopped");
-        return Ok(());
+    fn notify_health(&self, event: HealthEvent) {
+        info!("Health: {:?}", event);
+        self.write_log(SystemTime::now(), LOG_LAUNCHER, &event.to_string());
+        let mut handler = self.health_handler.lock().unwrap();
+        (handler)(event);
     }
 
-    let mut locked = state.lock().unwrap();
-    let child = locked
-        .as_mut()
-        .ok_or(io::Error::new(ErrorKind::NotFound, "no child pid"))?;
+    fn notify_hook(&self, event: HookEvent) {
+        info!("Hook: {:?}", event);
+        self.write_log(SystemTime::now(), LOG_LAUNCHER, &event.to_string());
+        let mut handler = self.hook_handler.lock().unwrap();
+        (handler)(event);
+    }
+}
 
-    let pid = child.id().to_string();
-    let status = if is_superuser {
-        Command::new(SUDO_COMMAND)
-            .arg("kill")
-            .arg("-INT")
-            .arg(pid)
-            .status()?
-    } else {
-        Command::new("kill").arg("-INT").arg(pid).status()?
+fn setup_unblocking(output: &dyn AsRawFd) {
+    let fd = output.as_raw_fd();
+    unsafe {
+        let flags = libc::fcntl(output.as_raw_fd(), libc::F_GETFL, 0);
+        libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK);
+    }
+}
+
+/// Open a descriptor which becomes readable once the process exits.
+///
+pub(crate) fn open_pidfd(pid: u32) -> Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid as libc::pid_t, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as libc::c_int) })
+}
+
+/// Fallback for kernels without pidfd: a pipe which is hung up once the process exits.
+///
+pub(crate) fn open_exit_pipe(pid: u32) -> Result<OwnedFd> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let (read, write) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    thread::spawn(move || {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        // WNOWAIT leaves the exited child to be reaped by `Child::try_wait`
+        let flags = libc::WEXITED | libc::WNOWAIT;
+        while unsafe { libc::waitid(libc::P_PID, pid, &mut info, flags) } != 0 {
+            if io::Error::last_os_error().kind() != ErrorKind::Interrupted {
+                break;
+            }
+        }
+        drop(write);
+    });
+    Ok(read)
+}
+
+/// Wait till any of the descriptors is ready, without timeout.
+///
+fn poll(fds: &mut [libc::pollfd]) -> Result<()> {
+    loop {
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } >= 0 {
+            return Ok(());
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Wait till the descriptor is writable, without timeout if it is not given.
+/// Returns false if the timeout expired.
+///
+fn poll_writable(fd: &dyn AsRawFd, timeout: Option<Duration>) -> Result<bool> {
+    let mut fds = [libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLOUT,
+        revents: 0,
+    }];
+    let timeout = timeout.map_or(-1, |timeout| timeout.as_millis() as libc::c_int);
+    loop {
+        match unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            ready => return Ok(ready > 0),
+        }
+    }
+}
+
+fn pollfd(fd: &dyn AsRawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd: fd.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// Forward the program output as soon as it appears and handle the program exit.
+///
+fn process_events(launcher: &Launcher, mut streams: Vec<OutputStream>, exit: OwnedFd) {
+    loop {
+        let mut fds: Vec<libc::pollfd> = streams.iter().map(|s| pollfd(&s.file)).collect();
+        fds.push(pollfd(&exit));
+        if let Err(e) = poll(&mut fds) {
+            error!("Error occurred while polling the program: {}", e);
+            break;
+        }
+
+        let mut ready = fds.iter().map(|fd| fd.revents != 0);
+        streams.retain_mut(|stream| !ready.next().unwrap() || process_output(launcher, stream));
+        if ready.next().unwrap() {
+            break;
+        }
+    }
+
+    // Collect the output written right before the exit
+    for stream in streams.iter_mut() {
+        if process_output(launcher, stream) {
+            flush_output(launcher, stream);
+        }
+    }
+    process_status(launcher);
+}
+
+/// Read everything available from the stream and pass the completed lines to the handler.
+/// Returns `false` once the stream is closed, passing the incomplete line too.
+///
+fn process_output(launcher: &Launcher, stream: &mut OutputStream) -> bool {
+    let mut buf = [0u8; 4096];
+    loop {
+        match stream.file.read(&mut buf) {
+            Ok(0) => {
+                debug!("Stream {} is closed", stream.stream);
+                flush_output(launcher, stream);
+                return false;
+            }
+            Ok(n) => {
+                let lines = stream.lines.push(&buf[..n]);
+                pass_output(launcher, stream, lines);
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            // the pseudo-terminal is hung up once the program exits
+            Err(e) if e.raw_os_error() == Some(libc::EIO) => {
+                debug!("Terminal of {} is closed", stream.stream);
+                flush_output(launcher, stream);
+                return false;
+            }
+            Err(e) => {
+                error!("Error occurred while reading {}: {}", stream.stream, e);
+                flush_output(launcher, stream);
+                return false;
+            }
+        }
+    }
+}
+
+/// Pass the incomplete line of the stream to the handler.
+///
+fn flush_output(launcher: &Launcher, stream: &mut OutputStream) {
+    let lines = stream.lines.flush().into_iter().collect();
+    pass_output(launcher, stream, lines);
+}
+
+/// Pass the lines to the handler and the log file.
+/// The output of the hook commands is logged under the hook name and never makes the program ready.
+///
+fn pass_output(launcher: &Launcher, stream: &OutputStream, lines: Vec<String>) {
+    if lines.is_empty() {
+        return;
+    }
+    let source = match stream.hook {
+        Some(hook) => hook.to_string(),
+        None => stream.stream.to_string(),
+    };
+    let secrets = launcher.shared.lock().masked.clone();
+    let mut handler = launcher.output_handler.lock().unwrap();
+    for text in lines {
+        let line = OutputLine::new(stream.stream, text);
+        let masked = mask_secrets(&line.text, &secrets);
+        trace!("{}: {}", source, masked);
+        launcher.write_log(line.timestamp, &source, &masked);
+        let is_ready = stream.hook.is_none()
+            && launcher
+                .ready_pattern
+                .as_ref()
+                .is_some_and(|re| re.is_match(&line.text));
+        (handler)(line);
+        if is_ready {
+            launcher.mark_ready();
+        }
+    }
+}
+
+/// Hide the values of the secret args, so they never get to the logs.
+///
+fn mask_secrets(text: &str, secrets: &[String]) -> String {
+    secrets
+        .iter()
+        .filter(|secret| !secret.is_empty())
+        .fold(text.to_string(), |text, secret| {
+            text.replace(secret.as_str(), "***")
+        })
+}
+
+fn process_status(launcher: &Launcher) {
+    let restart = match wait_child(&launcher.shared) {
+        Ok(Some(status)) => {
+            info!("Program exited with status: {}", status);
+            let event = format!("Program stopped with status {}", status);
+            launcher.write_log(SystemTime::now(), LOG_LAUNCHER, &event);
+            let restart = forget_child(launcher, Some(status));
+            let mut handler = launcher.status_handler.lock().unwrap();
+            (handler)(status);
+            drop(handler);
+            restart
+        }
+        Ok(None) => {
+            error!("Program is still running after the exit notification");
+            forget_child(launcher, None)
+        }
+        Err(e) => {
+            error!("Error occurred while waiting for the process: {}", e);
+            forget_child(launcher, None)
+        }
     };
+    process_post_stop(launcher);
+    process_restart(launcher, restart);
+}
+
+/// Run the post_stop hook commands of the exited program,
+/// keeping it active till they finish.
+///
+fn process_post_stop(launcher: &Launcher) {
+    if !launcher.shared.lock().cleanup_pending {
+        return;
+    }
+    let _ = launcher.run_hooks(Hook::PostStop);
+    let mut state = launcher.shared.lock();
+    state.cleanup_pending = false;
+    launcher.shared.changed.notify_all();
+}
+
+/// Start the exited program again as many times as the restart policy allows.
+///
+fn process_restart(launcher: &Launcher, mut restart: Option<RestartEvent>) {
+    while let Some(event) = restart.take() {
+        let RestartEvent::Scheduled {
+            attempt,
+            max_retries,
+            delay,
+        } = event
+        else {
+            launcher.notify_restart(event);
+            return;
+        };
+        launcher.notify_restart(event);
+
+        let state = launcher.shared.lock();
+        let (state, _) = launcher
+            .shared
+            .changed
+            .wait_timeout_while(state, delay, |state| !state.stop_requested)
+            .unwrap();
+        let cancelled = state.stop_requested;
+        drop(state);
+
+        let result = match cancelled {
+            true => Ok(()),
+            false => launcher.run_hooks(Hook::PreStart),
+        };
+        let mut state = launcher.shared.lock();
+        if state.stop_requested {
+            state.restart_pending = false;
+            launcher.shared.changed.notify_all();
+            drop(state);
+            launcher.notify_restart(RestartEvent::Cancelled);
+            return;
+        }
+
+        let (mut state, result) = match result {
+            Ok(()) => launcher.spawn(state),
+            Err(e) => (state, Err(e)),
+        };
+        match result {
+            Ok(()) => {
+                drop(state);
+                launcher.notify_restart(RestartEvent::Restarted {
+                    attempt,
+                    max_retries,
+                });
+                launcher.run_post_start();
+            }
+            Err(e) => {
+                error!("Failed to restart the program: {}", e);
+                restart = schedule_restart(launcher, &mut state, false);
+            }
+        }
+    }
+}
+
+/// Forget the exited child and decide whether it should be started again.
+/// The program stopped as failed is never successful.
+///
+fn forget_child(launcher: &Launcher, status: Option<ExitStatus>) -> Option<RestartEvent> {
+    let mut state = launcher.shared.lock();
+    state.child = None;
+    state.pty = None;
+    state.stdin = None;
+    state.session = None;
+    state.cleanup_pending = !launcher.hooks[&Hook::PostStop].is_empty();
+    let success = status.is_some_and(|status| status.success()) && !state.failed;
+    let restart = schedule_restart(launcher, &mut state, success);
+    launcher.shared.changed.notify_all();
+    restart
+}
+
+fn schedule_restart(launcher: &Launcher, state: &mut State, success: bool) -> Option<RestartEvent> {
+    state.restart_pending = false;
+    if state.stop_requested || !launcher.restart.is_required(success) {
+        return None;
+    }
+
+    let max_retries = launcher.restart.get_max_retries();
+    if state.retries >= max_retries {
+        return Some(RestartEvent::GaveUp {
+            retries: state.retries,
+        });
+    }
+
+    state.retries += 1;
+    state.restart_pending = true;
+    Some(RestartEvent::Scheduled {
+        attempt: state.retries,
+        max_retries,
+        delay: launcher.restart.get_backoff(state.retries),
+    })
+}
+
+/// Check the health of the running program till it exits,
+/// passing only the changes of the health to the handler.
+///
+fn process_health(launcher: &Launcher, health: &Health, pid: u32) {
+    let mut failures = 0;
+    let mut healthy = None;
+    loop {
+        let state = launcher.shared.lock();
+        let (state, _) = launcher
+            .shared
+            .changed
+            .wait_timeout_while(state, health.get_interval(), |state| {
+                state.is_running(pid) && !state.stop_requested
+            })
+            .unwrap();
+        if !state.is_running(pid) || state.stop_requested {
+            return;
+        }
+        drop(state);
+
+        let result = health::check(health.get_probe(), health.get_timeout(), &launcher.env);
+        let mut state = launcher.shared.lock();
+        if !state.is_running(pid) {
+            return; // the result is meaningless after the exit
+        }
+        state.healthy = result.is_ok();
+        launcher.shared.changed.notify_all();
+        drop(state);
+        let event = match result {
+            Ok(()) => {
+                failures = 0;
+                HealthEvent::Healthy
+            }
+            Err(e) => {
+                failures += 1;
+                debug!(
+                    "Health check {}/{} failed: {}",
+                    failures,
+                    health.get_failures(),
+                    e
+                );
+                if failures < health.get_failures() {
+                    continue;
+                }
+                HealthEvent::Unhealthy {
+                    reason: e.to_string(),
+                }
+            }
+        };
+        let is_healthy = event == HealthEvent::Healthy;
+        if healthy != Some(is_healthy) {
+            healthy = Some(is_healthy);
+            launcher.notify_health(event);
+        }
+
+        if !is_healthy && health.need_restart() {
+            fail(launcher, pid, "unhealthy");
+            return;
+        }
+    }
+}
 
-    match status.code() {
-        Some(0) => Ok(()),
-        Some(code) => {
-            let msg = format!("Kill command failed with status {}", code);
-            Err(io::Error::new(ErrorKind::Other, msg))
+/// Sample the resource usage of the running program till it exits.
+///
+fn process_stats(launcher: &Launcher, pid: u32) {
+    let mut sampler = Sampler::new(pid);
+    loop {
+        let state = launcher.shared.lock();
+        let (state, _) = launcher
+            .shared
+            .changed
+            .wait_timeout_while(state, STATS_INTERVAL, |state| state.is_running(pid))
+            .unwrap();
+        if !state.is_running(pid) {
+            return;
+        }
+        let uptime = state
+            .started_at
+            .map_or(Duration::ZERO, |time| time.elapsed());
+        drop(state);
+
+        match sampler.sample(uptime) {
+            Ok(stats) => {
+                let mut handler = launcher.stats_handler.lock().unwrap();
+                (handler)(stats);
+            }
+            Err(e) => debug!("Failed to sample the usage of {}: {}", pid, e),
         }
-        _ => Err(io::Error::new(ErrorKind::Other, "Kill command failed")),
-    }?;
+    }
+}
+
+/// Fail the start if the program does not print the ready line in time.
+///
+fn process_ready_timeout(launcher: &Launcher, timeout: Duration, pid: u32) {
+    let state = launcher.shared.lock();
+    let (state, _) = launcher
+        .shared
+        .changed
+        .wait_timeout_while(state, timeout, |state| {
+            state.is_running(pid) && !state.ready && !state.stop_requested
+        })
+        .unwrap();
+    if !state.is_running(pid) || state.ready || state.stop_requested {
+        return;
+    }
+    drop(state);
+
+    launcher.notify_ready(ReadyEvent::TimedOut { timeout });
+    fail(launcher, pid, "not ready");
+}
+
+/// Stop the failed program, so it is started again by the restart policy.
+///
+fn fail(launcher: &Launcher, pid: u32, reason: &str) {
+    let mut state = launcher.shared.lock();
+    if !state.is_running(pid) || state.stop_requested {
+        return;
+    }
+    state.failed = true;
+    drop(state);
+
+    if let Err(e) = terminate(launcher, pid) {
+        error!("Failed to stop the {} program: {}", reason, e);
+    }
+}
+
+fn wait_child(shared: &Shared) -> Result<Option<ExitStatus>> {
+    let mut locked = shared.lock();
+    if let Some(child) = locked.child.as_mut() {
+        child.try_wait()
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            "No child is running",
+        ))
+    }
+}
+
+fn stop(launcher: &Launcher, is_async: bool) -> Result<()> {
+    let mut state = launcher.shared.lock();
+    if !state.is_active() {
+        debug!("Already stopped");
+        return Ok(());
+    }
 
-    if is_async {
+    state.stop_requested = true;
+    launcher.shared.changed.notify_all();
+    let pid = state.child.as_ref().map(|child| child.id());
+    drop(state);
+
+    let Some(pid) = pid else {
+        debug!("Cancelling the pending restart");
+        if !is_async {
+            await_stopped(launcher);
+        }
         return Ok(());
+    };
+
+    terminate(launcher, pid)?;
+    if !is_async {
+        await_stopped(launcher);
     }
+    Ok(())
+}
+
+/// Run the pre_stop hook commands and send the configured stop signal to the program,
+/// escalating to `SIGTERM` and then `SIGKILL` after the grace timeout.
+///
+fn terminate(launcher: &Launcher, pid: u32) -> Result<()> {
+    // The pre_stop failure must not keep the program running
+    let _ = launcher.run_hooks(Hook::PreStop);
+
+    // Descendants are collected before the program exits and they get reparented,
+    // the cgroup holds them anyway
+    let has_cgroup = launcher.shared.lock().cgroup.is_some();
+    let escaped = match launcher.stop.need_kill_descendants() && !has_cgroup {
+        true => find_escaped(pid),
+        false => Vec::new(),
+    };
 
-    match child.wait() {
-        Ok(_) => {
+    let signal = launcher.stop.get_signal();
+    let timeout = launcher.stop.get_timeout();
+    kill(launcher, pid, &escaped, signal)?;
+    launcher.notify_stop(StopEvent::Signalled(signal));
+
+    let escalation: &[Signal] = match signal {
+        Signal::KILL => &[],
+        Signal::TERM => &[Signal::KILL],
+        _ => &[Signal::TERM, Signal::KILL],
+    };
+    for next in escalation {
+        if await_group_stopped(launcher, pid, &escaped, timeout) {
             debug!("Stopped gracefully");
-            *locked = None;
-            Ok(())
+            return Ok(());
+        }
+        kill(launcher, pid, &escaped, *next)?;
+        launcher.notify_stop(StopEvent::Escalated {
+            signal: *next,
+            timeout,
+        });
+    }
+    Ok(())
+}
+
+/// Wait till the program exits and its pending restart is cancelled.
+///
+fn await_stopped(launcher: &Launcher) {
+    let state = launcher.shared.lock();
+    let state = launcher
+        .shared
+        .changed
+        .wait_while(state, |state| state.is_active())
+        .unwrap();
+    drop(state);
+}
+
+/// Find descendants of the program which left its process group.
+///
+fn find_escaped(pid: u32) -> Vec<u32> {
+    match procfs::find_descendants(pid) {
+        Ok(descendants) => descendants
+            .into_iter()
+            .filter(|process| process.pgrp != pid)
+            .map(|process| process.pid)
+            .collect(),
+        Err(e) => {
+            warn!("Failed to find descendants of {}: {}", pid, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Wait till the program and the rest of its processes exit.
+/// Returns `false` if anything is still running after the timeout.
+///
+fn await_group_stopped(launcher: &Launcher, pgid: u32, escaped: &[u32], timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    let state = launcher.shared.lock();
+    let (state, _) = launcher
+        .shared
+        .changed
+        .wait_timeout_while(state, timeout, |state| state.is_running(pgid))
+        .unwrap();
+    if state.is_running(pgid) {
+        return false;
+    }
+    let cgroup = state.cgroup.clone();
+    drop(state);
+    let has_remaining = || match cgroup.as_ref() {
+        Some(cgroup) => cgroup.is_populated(),
+        None => has_remaining(pgid, escaped),
+    };
+    while has_remaining() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(GROUP_CHECK_INTERVAL);
+    }
+    true
+}
+
+/// Check if any process of the group or any escaped descendant is still running.
+///
+fn has_remaining(pgid: u32, escaped: &[u32]) -> bool {
+    match procfs::list_processes() {
+        Ok(processes) => processes.iter().any(|process| {
+            !process.is_zombie() && (process.pgrp == pgid || escaped.contains(&process.pid))
+        }),
+        Err(e) => {
+            warn!("Failed to list processes: {}", e);
+            false
+        }
+    }
+}
+
+/// Send the signal to the process group of the program and to the escaped descendants.
+/// The superuser program is signalled through its elevated session,
+/// otherwise the cgroup of the program reaches all its descendants.
+///
+fn kill(launcher: &Launcher, pgid: u32, escaped: &[u32], signal: Signal) -> Result<()> {
+    debug!("Sending {} to group {} and {:?}", signal, pgid, escaped);
+    if launcher.escalation.is_some() {
+        // the helper may be slow to read, so the pipe is written without the lock
+        let session = launcher.shared.lock().session.clone();
+        return match session {
+            Some(session) => session.kill(signal, escaped),
+            None => Ok(()), // already exited
+        };
+    }
+    let cgroup = launcher.shared.lock().cgroup.clone();
+    if let Some(cgroup) = cgroup {
+        debug!(
+            "Sending {} to cgroup {}",
+            signal,
+            cgroup.get_dir().display()
+        );
+        return cgroup.kill(signal);
+    }
+
+    let group = -(pgid as libc::pid_t);
+    let targets = escaped.iter().map(|pid| *pid as libc::pid_t);
+    for target in std::iter::once(group).chain(targets) {
+        if unsafe { libc::kill(target, signal.get_number()) } != 0 {
+            match io::Error::last_os_error() {
+                e if e.raw_os_error() == Some(libc::ESRCH) => {} // already exited
+                e => return Err(e),
+            }
         }
-        Err(e) => Err(e),
     }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::launcher::Launcher;
+    use crate::cgroup::find_root;
+    use crate::cgroup::tests::{use_mock_manager, SLOW_REPLY};
+    use crate::config::parse_content;
+    use crate::config::{Hook, Signal};
+    use crate::health::HealthEvent;
+    use crate::launcher::{
+        open_exit_pipe, open_pidfd, HookEvent, Launcher, ReadyEvent, RestartEvent, StopEvent,
+    };
+    use crate::output::{OutputLine, Stream};
+    use crate::stats::Stats;
     use env_logger::Env;
     use std::collections::HashMap;
-    use std::io::Write;
+    use std::io::{self, Write};
     use std::option::Option;
-    use std::process::ExitStatus;
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::path::Path;
+    use std::process::{Command, ExitStatus};
     use std::sync::{Arc, Mutex};
     use std::thread::sleep;
     use std::time::{Duration, Instant};
@@ -325,16 +1748,14 @@ mod tests {
         setup();
 
         let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
-        let output: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let output: Arc<Mutex<Option<OutputLine>>> = Arc::new(Mutex::new(None));
 
         let mut launcher = Launcher::test_new("echo test".parse().unwrap(), HashMap::new());
 
         let output_clone = Arc::clone(&output);
-        launcher.set_output_handler(move |str| {
+        launcher.set_output_handler(move |line| {
             let mut locked = output_clone.lock().unwrap();
-            if !str.is_empty() {
-                *locked = Some(str.clone());
-            }
+            *locked = Some(line);
         });
 
         let status_clone = Arc::clone(&status);
@@ -358,60 +1779,277 @@ mod tests {
         assert!(locked_status.unwrap().success());
         let locked_output = output.lock().unwrap();
         assert!(locked_output.is_some());
-        assert_eq!("test\n", locked_output.clone().unwrap().as_str());
+        let line = locked_output.clone().unwrap();
+        assert_eq!("test", line.text);
+        assert_eq!(Stream::Stdout, line.stream);
     }
 
     #[test]
-    fn execute_env() {
+    fn execute_argv() {
         setup();
 
-        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
-        let output: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = ["sh", "-c", "echo \"$$1\"", "sh", "$name"]
 
-        let env = HashMap::from([("VAR1".to_string(), "VAL1".to_string())]);
-        let mut launcher = Launcher::test_new("env".parse().unwrap(), env);
+          [args]
+          name = "two  'words'"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
 
+        let output: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
         let output_clone = Arc::clone(&output);
-        launcher.set_output_handler(move |str| {
-            let mut locked = output_clone.lock().unwrap();
-            if !str.is_empty() {
-                match locked.as_mut() {
-                    None => *locked = Some(str.clone()),
-                    Some(existing) => existing.push_str(&str),
-                }
-            }
+        launcher.set_output_handler(move |line| {
+            *output_clone.lock().unwrap() = Some(line.text);
         });
 
+        launcher.start().unwrap();
+
+        let output_clone = Arc::clone(&output);
+        await_condition(move || output_clone.lock().unwrap().is_some());
+        assert_eq!(output.lock().unwrap().as_deref(), Some("two  'words'"));
+    }
+
+    #[test]
+    fn execute_shell() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "echo $$0 | tr a-z A-Z && cd /tmp && pwd && exit 3"
+          shell = true
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
         let status_clone = Arc::clone(&status);
         launcher.set_status_handler(move |status| {
-            let mut locked = status_clone.lock().unwrap();
-            *locked = Some(status);
+            *status_clone.lock().unwrap() = Some(status);
         });
 
         launcher.start().unwrap();
 
         let status_clone = Arc::clone(&status);
-        let output_clone = Arc::clone(&output);
-        await_condition(move || {
-            let status_locked = status_clone.lock().unwrap();
-            let output_locked = output_clone.lock().unwrap();
-            status_locked.is_some() && output_locked.is_some()
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        assert_eq!(*lines.lock().unwrap(), ["/BIN/SH", "/tmp"]);
+        assert_eq!(status.lock().unwrap().unwrap().code(), Some(3));
+    }
+
+    #[test]
+    fn execute_on_terminal() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "test -t 1 && echo tty; stty size; read line; echo got $$line; sleep 0.5; stty size"
+          input = "hello\n"
+          shell = true
+          pty = true
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+        launcher.set_window_size(100, 30);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
         });
 
-        let locked_output = output.lock().unwrap();
-        assert!(locked_output.is_some());
-        assert!(locked_output
-            .clone()
-            .unwrap()
-            .lines()
-            .any(|line| line.contains("VAR1=VAL1")));
+        launcher.start().unwrap();
+
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || lines_clone.lock().unwrap().len() == 3);
+        launcher.set_window_size(120, 40);
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["tty", "30 100", "got hello", "40 120"]
+        );
+        assert!(status.lock().unwrap().unwrap().success());
     }
 
     #[test]
-    fn stop_process() {
+    fn write_input_and_eof() {
         setup();
 
-        let temp_file = NamedTempFile::new().unwrap();
+        for pty in [false, true] {
+            let program = parse_content(&format!(
+                r#"
+              id = "id1"
+              command = "sh -c 'while read line; do echo got $$line; done; echo eof'"
+              pty = {}
+            "#,
+                pty
+            ))
+            .unwrap();
+            let mut launcher = Launcher::new(&program);
+            assert!(launcher.write_input("early\n").is_err());
+
+            let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+            let lines_clone = Arc::clone(&lines);
+            launcher.set_output_handler(move |line| {
+                lines_clone.lock().unwrap().push(line.text);
+            });
+            let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+            let status_clone = Arc::clone(&status);
+            launcher.set_status_handler(move |status| {
+                *status_clone.lock().unwrap() = Some(status);
+            });
+
+            launcher.start().unwrap();
+            launcher.write_input("one\n").unwrap();
+            let lines_clone = Arc::clone(&lines);
+            await_condition(move || lines_clone.lock().unwrap().len() == 1);
+            launcher.write_input("two\n").unwrap();
+            let lines_clone = Arc::clone(&lines);
+            await_condition(move || lines_clone.lock().unwrap().len() == 2);
+            launcher.send_eof().unwrap();
+
+            let status_clone = Arc::clone(&status);
+            await_condition(move || status_clone.lock().unwrap().is_some());
+            assert_eq!(*lines.lock().unwrap(), ["got one", "got two", "eof"]);
+            assert!(status.lock().unwrap().unwrap().success());
+        }
+    }
+
+    #[test]
+    fn write_long_initial_input() {
+        setup();
+
+        // the program fills its output before it reads the input
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh -c 'yes | head -c 200000; wc -c'"
+          input = "{}"
+        "#,
+            "x".repeat(200_000)
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+
+        launcher.start().unwrap();
+        // the end of the input is not sent, so the program waits for it
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || lines_clone.lock().unwrap().len() == 100_000);
+        launcher.send_eof().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        assert_eq!(lines.lock().unwrap().last().unwrap(), "200000");
+    }
+
+    #[test]
+    fn write_long_input() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'sleep 1; wc -c'"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+
+        launcher.start().unwrap();
+        // more than the pipe holds, the rest is written once the program reads
+        let text = "x".repeat(200_000);
+        launcher.write_input(&text).unwrap();
+        assert!(launcher.write_input("y").is_err());
+        launcher.send_eof().unwrap();
+
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || !lines_clone.lock().unwrap().is_empty());
+        assert_eq!(*lines.lock().unwrap(), ["200000"]);
+    }
+
+    #[test]
+    fn execute_env() {
+        setup();
+
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let output: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+
+        let env = HashMap::from([("VAR1".to_string(), "VAL1".to_string())]);
+        let mut launcher = Launcher::test_new("env".parse().unwrap(), env);
+
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| {
+            let mut locked = output_clone.lock().unwrap();
+            match locked.as_mut() {
+                None => *locked = Some(line.text),
+                Some(existing) => existing.push_str(&format!("\n{}", line.text)),
+            }
+        });
+
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            let mut locked = status_clone.lock().unwrap();
+            *locked = Some(status);
+        });
+
+        launcher.start().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        let output_clone = Arc::clone(&output);
+        await_condition(move || {
+            let status_locked = status_clone.lock().unwrap();
+            let output_locked = output_clone.lock().unwrap();
+            status_locked.is_some() && output_locked.is_some()
+        });
+
+        let locked_output = output.lock().unwrap();
+        assert!(locked_output.is_some());
+        assert!(locked_output
+            .clone()
+            .unwrap()
+            .lines()
+            .any(|line| line.contains("VAR1=VAL1")));
+    }
+
+    #[test]
+    fn stop_process() {
+        setup();
+
+        let temp_file = NamedTempFile::new().unwrap();
 
         temp_file
             .as_file()
@@ -506,6 +2144,1051 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn restart_on_failure() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'exit 1'"
+
+          [restart]
+          policy = "on-failure"
+          max_retries = 2
+          backoff_base = 0.1
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<RestartEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_restart_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || {
+            let locked = events_clone.lock().unwrap();
+            locked.last() == Some(&RestartEvent::GaveUp { retries: 2 })
+        });
+
+        assert!(!launcher.is_running());
+        let locked = events.lock().unwrap();
+        assert_eq!(
+            *locked,
+            vec![
+                RestartEvent::Scheduled {
+                    attempt: 1,
+                    max_retries: 2,
+                    delay: Duration::from_millis(100)
+                },
+                RestartEvent::Restarted {
+                    attempt: 1,
+                    max_retries: 2
+                },
+                RestartEvent::Scheduled {
+                    attempt: 2,
+                    max_retries: 2,
+                    delay: Duration::from_millis(200)
+                },
+                RestartEvent::Restarted {
+                    attempt: 2,
+                    max_retries: 2
+                },
+                RestartEvent::GaveUp { retries: 2 },
+            ]
+        );
+    }
+
+    #[test]
+    fn no_restart_on_success() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "true"
+
+          [restart]
+          policy = "on-failure"
+          backoff_base = 0.1
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+        let events: Arc<Mutex<Vec<RestartEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_restart_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        assert!(!launcher.is_running());
+        assert!(events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn stop_cancels_restart() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "true"
+
+          [restart]
+          policy = "always"
+          backoff_base = 60
+          backoff_max = 60
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<RestartEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_restart_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || !events_clone.lock().unwrap().is_empty());
+        assert!(launcher.is_running());
+        assert!(launcher.start().is_err());
+
+        launcher.stop().unwrap();
+        assert!(!launcher.is_running());
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || {
+            let locked = events_clone.lock().unwrap();
+            locked.last() == Some(&RestartEvent::Cancelled)
+        });
+    }
+
+    #[test]
+    fn stop_escalates_to_kill() {
+        setup();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .write_all(
+                br#"
+          trap '' INT TERM
+          while true; do
+            sleep 0.1
+          done
+        "#,
+            )
+            .unwrap();
+
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh {}"
+
+          [stop]
+          signal = "INT"
+          timeout = 0.5
+        "#,
+            temp_file.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<StopEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_stop_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+        sleep(Duration::from_millis(200)); // let the shell install the trap
+        launcher.stop().unwrap();
+        assert!(!launcher.is_running());
+
+        let timeout = Duration::from_millis(500);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                StopEvent::Signalled(Signal::INT),
+                StopEvent::Escalated {
+                    signal: Signal::TERM,
+                    timeout
+                },
+                StopEvent::Escalated {
+                    signal: Signal::KILL,
+                    timeout
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn stop_with_custom_signal() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sleep 60"
+
+          [stop]
+          signal = "TERM"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<StopEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_stop_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+        launcher.stop().unwrap();
+        assert!(!launcher.is_running());
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![StopEvent::Signalled(Signal::TERM)]
+        );
+    }
+
+    #[test]
+    fn stop_superuser_through_session() {
+        setup();
+
+        // `env` stands for the escalation command, so the stop can't use plain kill
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'trap \"echo stopped; exit 0\" INT; echo started; while true; do sleep 0.1; done'"
+          superuser = true
+          escalation = ["env"]
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+
+        launcher.start().unwrap();
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || lines_clone.lock().unwrap().len() == 1);
+        launcher.stop().unwrap();
+        assert!(!launcher.is_running());
+        assert_eq!(*lines.lock().unwrap(), ["started", "stopped"]);
+    }
+
+    #[test]
+    fn execute_as_user() {
+        setup();
+        // switching the account directly requires the superuser rights
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'id -un; id -gn; echo $$USER'"
+          user = "nobody"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+        assert!(launcher.escalation.is_none());
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+
+        launcher.start().unwrap();
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        let group = Command::new("id").args(["-gn", "nobody"]).output().unwrap();
+        let group = String::from_utf8_lossy(&group.stdout).trim().to_string();
+        assert_eq!(*lines.lock().unwrap(), ["nobody", &group, "nobody"]);
+        assert!(status.lock().unwrap().unwrap().success());
+    }
+
+    #[test]
+    fn execute_as_user_with_clear_env() {
+        setup();
+        if unsafe { libc::geteuid() } != 0 {
+            return;
+        }
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'echo $$USER $$HOME'"
+          user = "nobody"
+          clear_env = true
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let output: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| {
+            *output_clone.lock().unwrap() = Some(line.text);
+        });
+
+        launcher.start().unwrap();
+        let output_clone = Arc::clone(&output);
+        await_condition(move || output_clone.lock().unwrap().is_some());
+        let home = Command::new("sh")
+            .args(["-c", "getent passwd nobody | cut -d: -f6"])
+            .output()
+            .unwrap();
+        let home = String::from_utf8_lossy(&home.stdout).trim().to_string();
+        assert_eq!(
+            output.lock().unwrap().as_deref(),
+            Some(format!("nobody {}", home).as_str())
+        );
+    }
+
+    #[test]
+    fn stop_process_group() {
+        setup();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .write_all(
+                br#"
+          sleep 60 &
+          echo $!
+          wait
+        "#,
+            )
+            .unwrap();
+
+        // background jobs of the shell ignore SIGINT
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh {}"
+
+          [stop]
+          signal = "TERM"
+        "#,
+            temp_file.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let output: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| {
+            *output_clone.lock().unwrap() = line.text;
+        });
+
+        launcher.start().unwrap();
+
+        let output_clone = Arc::clone(&output);
+        await_condition(move || !output_clone.lock().unwrap().is_empty());
+        let helper = output.lock().unwrap().clone();
+
+        launcher.stop().unwrap();
+        await_condition(move || !Path::new(&format!("/proc/{}", helper)).exists());
+    }
+
+    #[test]
+    fn stop_escaped_descendants() {
+        setup();
+
+        let temp_file = NamedTempFile::new().unwrap();
+        temp_file
+            .as_file()
+            .write_all(
+                br#"
+          setsid sleep 60 &
+          echo $!
+          wait
+        "#,
+            )
+            .unwrap();
+
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh {}"
+
+          [stop]
+          signal = "TERM"
+          kill_descendants = true
+        "#,
+            temp_file.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let output: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| {
+            *output_clone.lock().unwrap() = line.text;
+        });
+
+        launcher.start().unwrap();
+
+        let output_clone = Arc::clone(&output);
+        await_condition(move || !output_clone.lock().unwrap().is_empty());
+        let helper = output.lock().unwrap().clone();
+        sleep(Duration::from_millis(200)); // let the helper leave the group
+
+        launcher.stop().unwrap();
+        await_condition(move || !Path::new(&format!("/proc/{}", helper)).exists());
+    }
+
+    #[test]
+    fn serve_while_placing_into_scope() {
+        setup();
+
+        // the mock manager answers for the scope of `slow` after a delay, refusing it
+        let program = parse_content("id = 'slow'\ncommand = 'true'\n[cgroup]").unwrap();
+        let launcher = Launcher::new(&program);
+        let mut starting = launcher.clone();
+        let started = Instant::now();
+        let start = std::thread::spawn(move || {
+            let _manager = use_mock_manager();
+            starting.start()
+        });
+
+        let launcher_clone = launcher.clone();
+        await_condition(move || launcher_clone.is_running());
+        // answered without waiting for the manager
+        assert!(started.elapsed() < SLOW_REPLY / 2);
+
+        let res = start.join().unwrap();
+        assert!(started.elapsed() >= SLOW_REPLY);
+        assert!(res.err().unwrap().to_string().contains("already loaded"));
+        assert!(!launcher.is_running());
+    }
+
+    #[test]
+    fn stop_through_cgroup() {
+        setup();
+        // requires a writable cgroup v2 hierarchy, like a delegated one or as root
+        let Ok(parent) =
+            find_root().map(|root| root.join(format!("launcher-test-{}", std::process::id())))
+        else {
+            return;
+        };
+        if std::fs::create_dir(&parent).is_err() {
+            return;
+        }
+
+        // the helper leaves both the process group and the session
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh -c 'setsid sleep 60 & echo $$!; wait'"
+
+          [stop]
+          signal = "TERM"
+
+          [cgroup]
+          mode = "direct"
+          parent = "{}"
+        "#,
+            parent.display()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let output: Arc<Mutex<String>> = Arc::new(Mutex::new(String::new()));
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| {
+            *output_clone.lock().unwrap() = line.text;
+        });
+
+        launcher.start().unwrap();
+
+        let output_clone = Arc::clone(&output);
+        await_condition(move || !output_clone.lock().unwrap().is_empty());
+        let helper = output.lock().unwrap().clone();
+        sleep(Duration::from_millis(200)); // let the helper leave the group
+
+        launcher.stop().unwrap();
+        await_condition(move || !Path::new(&format!("/proc/{}", helper)).exists());
+        // the group of the program is removed with the last clone of the launcher
+        drop(launcher);
+        await_condition(move || std::fs::remove_dir(&parent).is_ok());
+    }
+
+    #[test]
+    fn report_stats() {
+        setup();
+
+        let stats: Arc<Mutex<Vec<Stats>>> = Arc::new(Mutex::new(Vec::new()));
+        let mut launcher = Launcher::test_new("sleep 10".to_string(), HashMap::new());
+        let stats_clone = Arc::clone(&stats);
+        launcher.set_stats_handler(move |sample| {
+            stats_clone.lock().unwrap().push(sample);
+        });
+
+        launcher.start().unwrap();
+        let pid = launcher.shared.lock().child.as_ref().unwrap().id();
+        let stats_clone = Arc::clone(&stats);
+        await_condition(move || !stats_clone.lock().unwrap().is_empty());
+        launcher.stop().unwrap();
+
+        let sample = stats.lock().unwrap()[0].clone();
+        assert_eq!(sample.pid, pid);
+        assert_eq!(sample.threads, 1);
+        assert!(sample.rss > 0);
+        assert!(sample.uptime >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn exit_reported_immediately() {
+        setup();
+
+        let status: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+        let mut launcher = Launcher::test_new("sleep 0.2".to_string(), HashMap::new());
+
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |_| {
+            *status_clone.lock().unwrap() = Some(Instant::now());
+        });
+
+        let started = Instant::now();
+        launcher.start().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+        let elapsed = status.lock().unwrap().unwrap() - started;
+        assert!(elapsed < Duration::from_millis(700), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn exit_descriptors() -> io::Result<()> {
+        setup();
+
+        let open: [fn(u32) -> io::Result<OwnedFd>; 2] = [open_pidfd, open_exit_pipe];
+        for open in open {
+            let mut child = Command::new("sleep").arg("0.2").spawn()?;
+            let fd = open(child.id())?;
+            assert!(!is_readable(&fd, 0));
+            assert!(is_readable(&fd, 5000));
+            // the exited child is left for reaping
+            assert!(child.try_wait()?.is_some());
+        }
+        Ok(())
+    }
+
+    fn is_readable(fd: &OwnedFd, timeout_ms: i32) -> bool {
+        let mut pollfd = libc::pollfd {
+            fd: fd.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        unsafe { libc::poll(&mut pollfd, 1, timeout_ms) > 0 }
+    }
+
+    #[test]
+    fn output_lines() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'printf \"out1\\nout\"; echo 2; echo err >&2; printf tail'"
+          max_line_length = 10
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let output: Arc<Mutex<Vec<OutputLine>>> = Arc::new(Mutex::new(Vec::new()));
+        let output_clone = Arc::clone(&output);
+        launcher.set_output_handler(move |line| output_clone.lock().unwrap().push(line));
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+
+        launcher.start().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+
+        let locked = output.lock().unwrap();
+        let stdout: Vec<&str> = locked
+            .iter()
+            .filter(|line| line.stream == Stream::Stdout)
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(stdout, ["out1", "out2", "tail"]);
+        let stderr: Vec<&str> = locked
+            .iter()
+            .filter(|line| line.stream == Stream::Stderr)
+            .map(|line| line.text.as_str())
+            .collect();
+        assert_eq!(stderr, ["err"]);
+    }
+
+    #[test]
+    fn write_log_file() {
+        setup();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh -c 'echo out; echo err >&2'"
+
+          [log]
+          path = "{}/$id.log"
+          timestamps = false
+        "#,
+            dir.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+
+        launcher.start().unwrap();
+
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+
+        let content = std::fs::read_to_string(dir.path().join("id1.log")).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", content);
+        assert!(lines[0].starts_with("[launcher] Started with pid"));
+        assert!(lines.contains(&"[stdout] out"));
+        assert!(lines.contains(&"[stderr] err"));
+        assert_eq!(
+            lines[3],
+            "[launcher] Program stopped with status exit status: 0"
+        );
+    }
+
+    #[test]
+    fn secret_args_resolved_at_start() {
+        setup();
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sh -c 'echo token=$token; read line; echo input=$$line'"
+          input = "$token\n"
+
+          [args]
+          token = "env:PROGRAM_TRAY_TEST_TOKEN"
+
+          [log]
+          path = "{}/$id.log"
+          timestamps = false
+        "#,
+            dir.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+        std::env::set_var("PROGRAM_TRAY_TEST_TOKEN", "t0ken");
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line: OutputLine| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let status: Arc<Mutex<Option<ExitStatus>>> = Arc::new(Mutex::new(None));
+        let status_clone = Arc::clone(&status);
+        launcher.set_status_handler(move |status| {
+            *status_clone.lock().unwrap() = Some(status);
+        });
+
+        launcher.start().unwrap();
+        let status_clone = Arc::clone(&status);
+        await_condition(move || status_clone.lock().unwrap().is_some());
+
+        assert_eq!(*lines.lock().unwrap(), ["token=t0ken", "input=t0ken"]);
+        let content = std::fs::read_to_string(dir.path().join("id1.log")).unwrap();
+        assert!(content.contains("[stdout] token=***"), "{}", content);
+        assert!(!content.contains("t0ken"), "{}", content);
+
+        std::env::remove_var("PROGRAM_TRAY_TEST_TOKEN");
+        let res = launcher.start();
+        let msg = res.err().unwrap().to_string();
+        assert!(msg.starts_with("Failed to resolve arg 'token'"), "{}", msg);
+        assert!(!launcher.is_running());
+    }
+
+    #[test]
+    fn prompted_args_required_at_start() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "echo code=$otp"
+
+          [args]
+          otp = { prompt = true }
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+        assert_eq!(launcher.get_prompts()[0].get_name(), "otp");
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line: OutputLine| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+
+        let res = launcher.start();
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+        assert!(!launcher.is_running());
+
+        launcher.set_prompted_args(HashMap::from([("otp".to_string(), "123456".to_string())]));
+        launcher.start().unwrap();
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || !lines_clone.lock().unwrap().is_empty());
+        assert_eq!(*lines.lock().unwrap(), ["code=123456"]);
+
+        // the values are entered again for every start
+        let launcher_clone = launcher.clone();
+        await_condition(move || !launcher_clone.is_running());
+        assert!(launcher.start().is_err());
+    }
+
+    #[test]
+    fn health_events() {
+        setup();
+
+        let marker = NamedTempFile::new().unwrap();
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "sleep 60"
+
+          [stop]
+          signal = "TERM"
+
+          [health]
+          kind = "command"
+          command = "test -e {}"
+          interval = 0.1
+          failures = 2
+        "#,
+            marker.path().to_str().unwrap()
+        ))
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<HealthEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_health_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || events_clone.lock().unwrap().len() == 1);
+        marker.close().unwrap();
+        let events_clone = Arc::clone(&events);
+        await_condition(move || events_clone.lock().unwrap().len() == 2);
+        assert!(launcher.is_running());
+        launcher.stop().unwrap();
+
+        let locked = events.lock().unwrap();
+        assert_eq!(locked[0], HealthEvent::Healthy);
+        assert!(
+            matches!(&locked[1], HealthEvent::Unhealthy { reason } if reason.contains("exit status: 1"))
+        );
+    }
+
+    #[test]
+    fn restart_unhealthy() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sleep 60"
+
+          [restart]
+          policy = "on-failure"
+          max_retries = 1
+          backoff_base = 0.1
+
+          [stop]
+          signal = "TERM"
+
+          [health]
+          kind = "command"
+          command = "false"
+          interval = 0.1
+          failures = 1
+          restart = true
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let health: Arc<Mutex<u32>> = Arc::new(Mutex::new(0));
+        let health_clone = Arc::clone(&health);
+        launcher.set_health_handler(move |_| {
+            *health_clone.lock().unwrap() += 1;
+        });
+        let events: Arc<Mutex<Vec<RestartEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_restart_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || {
+            let locked = events_clone.lock().unwrap();
+            locked.last() == Some(&RestartEvent::GaveUp { retries: 1 })
+        });
+        assert!(!launcher.is_running());
+        assert_eq!(*health.lock().unwrap(), 2);
+        assert_eq!(events.lock().unwrap().len(), 3);
+    }
+
+    #[test]
+    fn ready_on_output() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'echo starting; sleep 0.2; echo Listening on 8080; echo Listening on 8081; sleep 60'"
+          ready_pattern = "^Listening on \\d+$"
+          ready_timeout = 5
+
+          [stop]
+          signal = "TERM"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<ReadyEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_ready_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let events_clone = Arc::clone(&events);
+        await_condition(move || !events_clone.lock().unwrap().is_empty());
+        sleep(Duration::from_millis(200)); // the second matching line is ignored
+        launcher.stop().unwrap();
+
+        let locked = events.lock().unwrap();
+        assert_eq!(locked.len(), 1);
+        let ReadyEvent::Ready { elapsed } = locked[0] else {
+            panic!("Unexpected event {:?}", locked[0]);
+        };
+        assert!(elapsed >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn ready_timeout_fails_start() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sleep 60"
+          ready_pattern = "Listening"
+          ready_timeout = 0.3
+
+          [restart]
+          policy = "on-failure"
+          max_retries = 1
+          backoff_base = 0.1
+
+          [stop]
+          signal = "TERM"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let events: Arc<Mutex<Vec<ReadyEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_ready_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+        let restarts: Arc<Mutex<Vec<RestartEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let restarts_clone = Arc::clone(&restarts);
+        launcher.set_restart_handler(move |event| {
+            restarts_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+
+        let restarts_clone = Arc::clone(&restarts);
+        await_condition(move || {
+            let locked = restarts_clone.lock().unwrap();
+            locked.last() == Some(&RestartEvent::GaveUp { retries: 1 })
+        });
+        assert!(!launcher.is_running());
+        let timeout = Duration::from_millis(300);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                ReadyEvent::TimedOut { timeout },
+                ReadyEvent::TimedOut { timeout }
+            ]
+        );
+    }
+
+    #[test]
+    fn hooks_around_program() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sh -c 'echo main; sleep 60'"
+          pre_start = ["echo pre start", "sh -c 'echo $NAME'"]
+          pre_stop = ["echo pre stop"]
+          post_stop = ["sh -c 'sleep 0.2; echo post stop'"]
+
+          [env]
+          NAME = "hook env"
+
+          [stop]
+          signal = "TERM"
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<String>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line: OutputLine| {
+            lines_clone.lock().unwrap().push(line.text);
+        });
+        let events: Arc<Mutex<Vec<HookEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_hook_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        launcher.start().unwrap();
+        let lines_clone = Arc::clone(&lines);
+        await_condition(move || lines_clone.lock().unwrap().len() == 3);
+        launcher.stop().unwrap();
+
+        // stop waits for the post_stop hook
+        assert!(!launcher.is_running());
+        assert_eq!(
+            *lines.lock().unwrap(),
+            ["pre start", "hook env", "main", "pre stop", "post stop"]
+        );
+        let hooks: Vec<Hook> = events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| match event {
+                HookEvent::Started { hook, .. } => *hook,
+                HookEvent::Failed { .. } => panic!("Unexpected event {:?}", event),
+            })
+            .collect();
+        assert_eq!(
+            hooks,
+            [
+                Hook::PreStart,
+                Hook::PreStart,
+                Hook::PreStop,
+                Hook::PostStop
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_pre_start_aborts_start() {
+        setup();
+
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "sleep 60"
+          pre_start = ["sh -c 'echo no network >&2; exit 2'", "echo never"]
+        "#,
+        )
+        .unwrap();
+        let mut launcher = Launcher::new(&program);
+
+        let lines: Arc<Mutex<Vec<OutputLine>>> = Arc::new(Mutex::new(Vec::new()));
+        let lines_clone = Arc::clone(&lines);
+        launcher.set_output_handler(move |line: OutputLine| {
+            lines_clone.lock().unwrap().push(line);
+        });
+        let events: Arc<Mutex<Vec<HookEvent>>> = Arc::new(Mutex::new(Vec::new()));
+        let events_clone = Arc::clone(&events);
+        launcher.set_hook_handler(move |event| {
+            events_clone.lock().unwrap().push(event);
+        });
+
+        let res = launcher.start();
+        assert!(res.err().unwrap().to_string().contains("exit status: 2"));
+        assert!(!launcher.is_running());
+
+        let locked = lines.lock().unwrap();
+        assert_eq!(locked.len(), 1);
+        assert_eq!(locked[0].text, "no network");
+        assert_eq!(locked[0].stream, Stream::Stderr);
+        let locked = events.lock().unwrap();
+        assert_eq!(locked.len(), 2);
+        assert!(matches!(
+            locked[1],
+            HookEvent::Failed {
+                hook: Hook::PreStart,
+                ..
+            }
+        ));
+    }
+
     fn await_condition<F>(predicate: F)
     where
         F: Fn() -> bool + Send + 'static,
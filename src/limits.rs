@@ -0,0 +1,99 @@
+use crate::config::{IoClass, Limits};
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+
+/// `ioprio_set` target: a single process
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+/// The class is kept in the upper bits of the I/O priority value
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// Setup the command to run the program with the limits and priorities,
+/// both soft and hard limits are set. The start fails if any of them can't be applied,
+/// e.g. a limit above the inherited hard one or a higher priority without the rights.
+///
+pub fn apply(limits: &Limits, command: &mut Command) {
+    let rlimits: Vec<_> = [
+        (libc::RLIMIT_NOFILE, limits.get_open_files()),
+        (libc::RLIMIT_AS, limits.get_address_space()),
+        (libc::RLIMIT_CPU, limits.get_cpu_time()),
+        (libc::RLIMIT_CORE, limits.get_core_size()),
+    ]
+    .into_iter()
+    .filter_map(|(resource, value)| Some((resource, value?)))
+    .collect();
+    let nice = limits.get_nice();
+    let io_priority = limits.get_io_priority().map(|(class, priority)| {
+        let class = match class {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        };
+        (class << IOPRIO_CLASS_SHIFT) | priority as libc::c_int
+    });
+    if rlimits.is_empty() && nice.is_none() && io_priority.is_none() {
+        return;
+    }
+
+    unsafe {
+        command.pre_exec(move || {
+            for (resource, value) in rlimits.iter() {
+                let limit = libc::rlimit {
+                    rlim_cur: *value,
+                    rlim_max: *value,
+                };
+                check(libc::setrlimit(*resource, &limit))?;
+            }
+            if let Some(nice) = nice {
+                check(libc::setpriority(libc::PRIO_PROCESS, 0, nice))?;
+            }
+            if let Some(io_priority) = io_priority {
+                let result =
+                    libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, io_priority);
+                check(result as libc::c_int)?;
+            }
+            Ok(())
+        });
+    }
+}
+
+fn check(result: libc::c_int) -> io::Result<()> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_content;
+
+    #[test]
+    fn apply_limits() {
+        let program = parse_content(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [limits]
+          open_files = 512
+          core_size = 0
+          cpu_time = 3600
+          nice = 10
+          io_class = "idle"
+        "#,
+        )
+        .unwrap();
+
+        let mut command = Command::new("sh");
+        command.args(["-c", "ulimit -n; ulimit -c; ulimit -t; nice; ionice -p $$"]);
+        apply(program.get_limits(), &mut command);
+        let output = command.output().unwrap();
+        assert!(output.status.success());
+        assert_eq!(
+            String::from_utf8_lossy(&output.stdout),
+            "512\n0\n3600\n10\nidle\n"
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use crate::config::Log;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Program log on disk, rotated by size: `file.log` is renamed to `file.log.1`,
+/// `file.log.1` to `file.log.2` and so on.
+///
+pub struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    rotate: u32,
+    timestamps: bool,
+    file: Option<File>,
+    size: u64,
+}
+
+impl LogFile {
+    pub fn new(log: &Log, id: &str) -> Self {
+        LogFile {
+            path: log.get_path(id),
+            max_size: log.get_max_size(),
+            rotate: log.get_rotate(),
+            timestamps: log.need_timestamps(),
+            file: None,
+            size: 0,
+        }
+    }
+
+    /// Append the line, marked with its source, e.g. `stdout`.
+    ///
+    pub fn write_line(&mut self, time: SystemTime, source: &str, text: &str) -> io::Result<()> {
+        let line = match self.timestamps {
+            true => format!("{} [{}] {}\n", format_timestamp(time), source, text),
+            false => format!("[{}] {}\n", source, text),
+        };
+
+        if self.file.is_none() {
+            self.open()?;
+        }
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+            self.open()?;
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(line.as_bytes())?;
+            self.size += line.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.rotate == 0 {
+            return File::create(&self.path).map(|_| ());
+        }
+        for n in (1..self.rotate).rev() {
+            let from = rotated_path(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, n + 1))?;
+            }
+        }
+        fs::rename(&self.path, rotated_path(&self.path, 1))
+    }
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+/// Local time in `YYYY-MM-DD HH:MM:SS.mmm` format
+///
+fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs() as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe { libc::localtime_r(&secs, &mut tm) };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:03}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min,
+        tm.tm_sec,
+        since_epoch.subsec_millis()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::parse_content;
+    use tempfile::TempDir;
+
+    fn open_log(dir: &TempDir, options: &str) -> LogFile {
+        let program = parse_content(&format!(
+            r#"
+          id = "id1"
+          command = "command1"
+
+          [log]
+          path = "{}/logs/$id.log"
+          {}
+        "#,
+            dir.path().to_str().unwrap(),
+            options
+        ))
+        .unwrap();
+        LogFile::new(program.get_log().unwrap(), program.get_id())
+    }
+
+    #[test]
+    fn write_lines() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let mut log = open_log(&dir, "timestamps = false");
+        log.write_line(SystemTime::now(), "stdout", "line1")?;
+        log.write_line(SystemTime::now(), "stderr", "line2")?;
+
+        let content = fs::read_to_string(dir.path().join("logs/id1.log"))?;
+        assert_eq!(content, "[stdout] line1\n[stderr] line2\n");
+        Ok(())
+    }
+
+    #[test]
+    fn write_timestamps() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let mut log = open_log(&dir, "");
+        log.write_line(SystemTime::now(), "stdout", "line1")?;
+
+        let content = fs::read_to_string(dir.path().join("logs/id1.log"))?;
+        let (timestamp, line) = content.split_at(23);
+        assert_eq!(line, " [stdout] line1\n");
+        assert_eq!(&timestamp[4..5], "-");
+        assert_eq!(&timestamp[19..20], ".");
+        Ok(())
+    }
+
+    #[test]
+    fn rotate_files() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        // every line takes 12 bytes, so only one fits in the file
+        let mut log = open_log(&dir, "timestamps = false\nmax_size = 20\nrotate = 2");
+        for n in 1..=4 {
+            log.write_line(SystemTime::now(), "out", &format!("line{}", n))?;
+        }
+
+        let read = |name: &str| fs::read_to_string(dir.path().join("logs").join(name));
+        assert_eq!(read("id1.log")?, "[out] line4\n");
+        assert_eq!(read("id1.log.1")?, "[out] line3\n");
+        assert_eq!(read("id1.log.2")?, "[out] line2\n");
+        assert!(read("id1.log.3").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_without_rotation() -> io::Result<()> {
+        let dir = TempDir::new()?;
+        let mut log = open_log(&dir, "timestamps = false\nmax_size = 20\nrotate = 0");
+        log.write_line(SystemTime::now(), "out", "line1")?;
+        log.write_line(SystemTime::now(), "out", "line2")?;
+
+        let content = fs::read_to_string(dir.path().join("logs/id1.log"))?;
+        assert_eq!(content, "[out] line2\n");
+        assert!(!dir.path().join("logs/id1.log.1").exists());
+        Ok(())
+    }
+}
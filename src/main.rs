@@ -3,16 +3,32 @@
 //! This UI application can wrap any CLI-program or service in a tray for background work.
 //!
 
+mod account;
+mod cgroup;
 mod config;
+mod dotenv;
+mod environment;
+mod escalation;
+mod group;
+mod health;
 mod launcher;
+mod limits;
+mod logfile;
+mod output;
+mod procfs;
+mod pty;
+mod secret;
+mod stats;
+mod template;
 mod ui;
 
 use crate::config::Program;
-use crate::launcher::Launcher;
+use crate::group::Group;
 use crate::ui::icons::Icons;
 use anyhow::Result;
 use clap::Parser;
 use env_logger::Env;
+use gtk::glib;
 use log::debug;
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -25,9 +41,9 @@ struct Args {
     #[arg(short, long)]
     check_only: bool,
 
-    /// Path to config file
-    #[arg(value_name = "PATH")]
-    file_path: String,
+    /// Paths to config files
+    #[arg(value_name = "PATH", required = true)]
+    file_paths: Vec<String>,
 }
 
 fn main() -> Result<()> {
@@ -35,30 +51,43 @@ fn main() -> Result<()> {
 
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).try_init()?;
 
-    println!("Loading config file: '{}'", args.file_path);
-    let program = config::parse_properties_file(&args.file_path)?;
-    println!("Found program '{}'", program.get_id());
+    for file_path in args.file_paths.iter() {
+        println!("Loading config file: '{}'", file_path);
+    }
+    let programs = config::parse_properties_files(&args.file_paths)?;
+    for program in programs.iter() {
+        println!("Found program '{}'", program.get_id());
+    }
 
-    let icons = ui::icons::load_icons(&program)?;
+    // The tray icon shows all programs, so its icons are taken from the first one
+    let icons = ui::icons::load_icons(&programs[0])?;
 
-    let launcher = Rc::new(RefCell::new(Launcher::new(&program)));
+    let group = Rc::new(RefCell::new(Group::new(&programs)?));
 
     if args.check_only {
         println!("Check completed")
     } else {
-        run_ui(&program, &icons, &launcher)?
+        run_ui(&programs, &icons, &group)?
     }
 
-    stop_if_running(&launcher)?;
+    stop_if_running(&group)?;
     Ok(())
 }
 
-fn run_ui(program: &Program, icons: &Icons, launcher: &Rc<RefCell<Launcher>>) -> Result<()> {
+fn run_ui(programs: &[Program], icons: &Icons, group: &Rc<RefCell<Group>>) -> Result<()> {
     debug!("Running UI");
     gtk::init()?;
 
+    // The program runs in its own session, so the tray has to stop it on interrupt
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        glib::unix_signal_add_local(signal, || {
+            gtk::main_quit();
+            glib::ControlFlow::Break
+        });
+    }
+
     debug!("Initializing program tray");
-    let mut app = ui::app::App::new(&program, &icons, &launcher);
+    let mut app = ui::app::App::new(programs, icons, group);
     app.start();
 
     debug!("UI started");
@@ -68,11 +97,11 @@ fn run_ui(program: &Program, icons: &Icons, launcher: &Rc<RefCell<Launcher>>) ->
     Ok(())
 }
 
-fn stop_if_running(launcher: &Rc<RefCell<Launcher>>) -> Result<()> {
-    let mut launcher = launcher.borrow_mut();
-    if launcher.is_running() {
-        println!("Shutting down running program");
-        launcher.stop()?;
+fn stop_if_running(group: &Rc<RefCell<Group>>) -> Result<()> {
+    let group = group.borrow();
+    if (0..group.count()).any(|index| group.is_running(index)) {
+        println!("Shutting down running programs");
+        group.stop_all()?;
     }
 
     Ok(())
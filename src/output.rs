@@ -0,0 +1,143 @@
+use std::fmt;
+use std::time::SystemTime;
+
+/// Output stream of the program
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl fmt::Display for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+/// Complete line of the program output, without the line terminator
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputLine {
+    pub stream: Stream,
+    pub text: String,
+    pub timestamp: SystemTime,
+}
+
+impl OutputLine {
+    pub fn new(stream: Stream, text: String) -> Self {
+        OutputLine {
+            stream,
+            text,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+/// Splits raw output into lines.
+/// Lines longer than the limit are split, but never inside of a UTF-8 sequence.
+///
+pub struct LineBuffer {
+    pending: Vec<u8>,
+    max_length: usize,
+}
+
+impl LineBuffer {
+    pub fn new(max_length: usize) -> Self {
+        LineBuffer {
+            pending: Vec::new(),
+            max_length: max_length.max(4), // room for any UTF-8 character
+        }
+    }
+
+    /// Append the raw data and take the completed lines.
+    ///
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        self.pending.extend_from_slice(data);
+
+        let mut lines = Vec::new();
+        loop {
+            let newline = self.pending.iter().position(|b| *b == b'\n');
+            match newline {
+                Some(pos) if pos <= self.max_length => {
+                    let mut line: Vec<u8> = self.pending.drain(..=pos).collect();
+                    line.pop();
+                    if line.last() == Some(&b'\r') {
+                        line.pop();
+                    }
+                    lines.push(decode(&line));
+                }
+                _ if self.pending.len() > self.max_length => {
+                    let end = char_boundary(&self.pending, self.max_length);
+                    let line: Vec<u8> = self.pending.drain(..end).collect();
+                    lines.push(decode(&line));
+                }
+                _ => return lines,
+            }
+        }
+    }
+
+    /// Take the last incomplete line, if any.
+    ///
+    pub fn flush(&mut self) -> Option<String> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let line: Vec<u8> = self.pending.drain(..).collect();
+        Some(decode(&line))
+    }
+}
+
+fn decode(line: &[u8]) -> String {
+    String::from_utf8_lossy(line).into_owned()
+}
+
+/// Find the split position not greater than `max`, which doesn't break a UTF-8 sequence.
+///
+fn char_boundary(data: &[u8], max: usize) -> usize {
+    // continuation bytes look like 0b10xxxxxx
+    let is_continuation = |b: u8| b & 0xC0 == 0x80;
+    (max.saturating_sub(3)..=max)
+        .rev()
+        .find(|pos| !is_continuation(data[*pos]))
+        .filter(|pos| *pos > 0)
+        .unwrap_or(max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines() {
+        let mut buffer = LineBuffer::new(100);
+        assert!(buffer.push(b"hel").is_empty());
+        assert_eq!(
+            buffer.push(b"lo\r\nworld\n\npartial"),
+            ["hello", "world", ""]
+        );
+        assert_eq!(buffer.flush(), Some("partial".to_string()));
+        assert_eq!(buffer.flush(), None);
+    }
+
+    #[test]
+    fn keep_multibyte_characters() {
+        let mut buffer = LineBuffer::new(100);
+        let text = "привет\n".as_bytes();
+        assert!(buffer.push(&text[..3]).is_empty());
+        assert_eq!(buffer.push(&text[3..]), ["привет"]);
+    }
+
+    #[test]
+    fn split_long_lines() {
+        let mut buffer = LineBuffer::new(5);
+        assert_eq!(buffer.push(b"0123456789ab\n"), ["01234", "56789", "ab"]);
+
+        // "й" takes 2 bytes, so it doesn't fit in the rest of the first line
+        let mut buffer = LineBuffer::new(5);
+        assert_eq!(buffer.push("abcdй\n".as_bytes()), ["abcd", "й"]);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs;
+use std::io;
+
+/// Process details from `/proc/<pid>/stat`
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stat {
+    pub pid: u32,
+    pub state: char,
+    pub ppid: u32,
+    pub pgrp: u32,
+}
+
+impl Stat {
+    /// Check if the process is exited, but not reaped by its parent yet.
+    ///
+    pub fn is_zombie(&self) -> bool {
+        self.state == 'Z'
+    }
+}
+
+/// Resource usage of the process from `/proc/<pid>/stat`
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Usage {
+    /// User and system CPU time, in clock ticks
+    pub cpu_ticks: u64,
+    pub threads: u32,
+    /// Resident set size, in pages
+    pub rss_pages: u64,
+}
+
+/// Read the status of the process.
+///
+pub fn read_stat(pid: u32) -> io::Result<Stat> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    parse_stat(&content)
+}
+
+/// Read the resource usage of the process.
+///
+pub fn read_usage(pid: u32) -> io::Result<Usage> {
+    let content = fs::read_to_string(format!("/proc/{}/stat", pid))?;
+    parse_usage(&content)
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed stat")
+}
+
+/// Split the stat into the pid and the fields after the command name,
+/// which is enclosed in parentheses and may contain spaces.
+///
+fn split_stat(content: &str) -> io::Result<(&str, std::str::SplitWhitespace<'_>)> {
+    let (pid, rest) = content.split_once(" (").ok_or_else(invalid)?;
+    let (_, rest) = rest.rsplit_once(") ").ok_or_else(invalid)?;
+    Ok((pid, rest.split_whitespace()))
+}
+
+fn parse_stat(content: &str) -> io::Result<Stat> {
+    // state ppid pgrp ...
+    let (pid, mut fields) = split_stat(content)?;
+    let state = fields.next().and_then(|field| field.chars().next());
+    let mut next = || -> io::Result<u32> {
+        let field = fields.next().ok_or_else(invalid)?;
+        field.parse().map_err(|_| invalid())
+    };
+    Ok(Stat {
+        pid: pid.trim().parse().map_err(|_| invalid())?,
+        state: state.ok_or_else(invalid)?,
+        ppid: next()?,
+        pgrp: next()?,
+    })
+}
+
+fn parse_usage(content: &str) -> io::Result<Usage> {
+    // the fields are numbered from 1 by proc(5), the first 2 are before the command name end
+    let (_, fields) = split_stat(content)?;
+    let fields: Vec<&str> = fields.collect();
+    let field = |number: usize| -> io::Result<u64> {
+        let field = fields.get(number - 3).ok_or_else(invalid)?;
+        field.parse().map_err(|_| invalid())
+    };
+    Ok(Usage {
+        cpu_ticks: field(14)? + field(15)?, // utime + stime
+        threads: field(20)? as u32,
+        rss_pages: field(24)?,
+    })
+}
+
+/// List every running process.
+///
+pub fn list_processes() -> io::Result<Vec<Stat>> {
+    let mut processes = Vec::new();
+    for entry in fs::read_dir("/proc")? {
+        let pid = entry?.file_name().to_str().and_then(|s| s.parse().ok());
+        if let Some(pid) = pid {
+            // the process may exit while listing
+            if let Ok(stat) = read_stat(pid) {
+                processes.push(stat);
+            }
+        }
+    }
+    Ok(processes)
+}
+
+/// Find all descendants of the process: children, their children and so on.
+///
+pub fn find_descendants(pid: u32) -> io::Result<Vec<Stat>> {
+    let processes = list_processes()?;
+    let mut descendants: Vec<Stat> = Vec::new();
+    let mut parents = vec![pid];
+    while let Some(parent) = parents.pop() {
+        for process in processes.iter().filter(|p| p.ppid == parent) {
+            parents.push(process.pid);
+            descendants.push(process.clone());
+        }
+    }
+    Ok(descendants)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::{Command, Stdio};
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_stat_with_spaces() -> io::Result<()> {
+        let stat = parse_stat("42 (my (odd) prog) S 1 42 42 0 -1 4194560 100")?;
+        assert_eq!(
+            stat,
+            Stat {
+                pid: 42,
+                state: 'S',
+                ppid: 1,
+                pgrp: 42
+            }
+        );
+        assert!(parse_stat("7 (zombie) Z 1 7 7").unwrap().is_zombie());
+        assert!(parse_stat("garbage").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn parse_usage_fields() -> io::Result<()> {
+        let content = "42 (my prog) S 1 42 42 0 -1 4194560 100 0 0 0 \
+                       250 50 0 0 20 0 7 0 1000 104857600 2560 18446744073709551615";
+        let usage = parse_usage(content)?;
+        assert_eq!(
+            usage,
+            Usage {
+                cpu_ticks: 300,
+                threads: 7,
+                rss_pages: 2560
+            }
+        );
+        assert!(parse_usage("7 (short) S 1 7 7").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_own_stat() -> io::Result<()> {
+        let stat = read_stat(std::process::id())?;
+        assert_eq!(stat.pid, std::process::id());
+        assert!(read_usage(std::process::id())?.threads >= 1);
+        Ok(())
+    }
+
+    #[test]
+    fn find_grandchildren() -> io::Result<()> {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg("sleep 5 & wait")
+            .stdout(Stdio::null())
+            .spawn()?;
+        sleep(Duration::from_millis(200));
+
+        let descendants = find_descendants(std::process::id())?;
+        let _ = child.kill();
+        let _ = child.wait();
+
+        let shell = descendants.iter().find(|p| p.pid == child.id());
+        assert!(shell.is_some());
+        assert!(descendants.iter().any(|p| p.ppid == child.id()));
+        Ok(())
+    }
+}
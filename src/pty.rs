@@ -0,0 +1,99 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd};
+
+/// Size of the terminal till the window tells its own, in characters
+pub const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// Pseudo-terminal of the program: the launcher reads and writes the master side,
+/// the slave one becomes the standard streams and the controlling terminal of the program.
+///
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+/// Open the pseudo-terminal of the size in characters.
+/// The echo is off, so the input written by the launcher doesn't get to the output.
+///
+pub fn open((columns, rows): (u16, u16)) -> io::Result<Pty> {
+    let flags = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
+    let master = check(unsafe { libc::posix_openpt(flags) })?;
+    let master = unsafe { File::from_raw_fd(master) };
+    check(unsafe { libc::grantpt(master.as_raw_fd()) })?;
+    check(unsafe { libc::unlockpt(master.as_raw_fd()) })?;
+
+    let mut name = [0 as libc::c_char; 128];
+    match unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr(), name.len()) } {
+        0 => {}
+        error => return Err(io::Error::from_raw_os_error(error)),
+    }
+    let name = unsafe { CStr::from_ptr(name.as_ptr()) };
+    let slave = check(unsafe { libc::open(name.as_ptr(), flags) })?;
+    let slave = unsafe { File::from_raw_fd(slave) };
+
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::tcgetattr(slave.as_raw_fd(), &mut termios) })?;
+    termios.c_lflag &= !(libc::ECHO | libc::ECHONL);
+    check(unsafe { libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) })?;
+
+    set_window_size(&master, (columns, rows))?;
+    Ok(Pty { master, slave })
+}
+
+/// Resize the terminal, the program gets `SIGWINCH`.
+///
+pub fn set_window_size(master: &dyn AsRawFd, (columns, rows): (u16, u16)) -> io::Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: columns,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    check(unsafe { libc::ioctl(master.as_raw_fd(), libc::TIOCSWINSZ, &size) })?;
+    Ok(())
+}
+
+/// End-of-file character of the terminal, `^D` by default.
+///
+pub fn eof_char(master: &dyn AsRawFd) -> io::Result<u8> {
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    check(unsafe { libc::tcgetattr(master.as_raw_fd(), &mut termios) })?;
+    Ok(termios.c_cc[libc::VEOF])
+}
+
+fn check(result: libc::c_int) -> io::Result<libc::c_int> {
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        result => Ok(result),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    #[test]
+    fn open_terminal() -> io::Result<()> {
+        let mut pty = open((100, 30))?;
+        assert_eq!(unsafe { libc::isatty(pty.slave.as_raw_fd()) }, 1);
+
+        set_window_size(&pty.master, (120, 40))?;
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        check(unsafe { libc::ioctl(pty.slave.as_raw_fd(), libc::TIOCGWINSZ, &mut size) })?;
+        assert_eq!((size.ws_col, size.ws_row), (120, 40));
+        assert_eq!(eof_char(&pty.master)?, 4);
+
+        // the input line is not echoed back
+        pty.master.write_all(b"input\n")?;
+        let mut buf = [0u8; 16];
+        let n = pty.slave.read(&mut buf)?;
+        assert_eq!(&buf[..n], b"input\n");
+        pty.slave.write_all(b"output\n")?;
+        let n = pty.master.read(&mut buf)?;
+        assert_eq!(&buf[..n], b"output\r\n");
+        Ok(())
+    }
+}
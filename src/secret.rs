@@ -0,0 +1,256 @@
+use crate::config::Secret;
+use gtk::gio::{self, BusType, Cancellable, DBusCallFlags, DBusConnection};
+use gtk::glib::variant::{ObjectPath, ToVariant};
+use gtk::glib::{Variant, VariantTy};
+use log::debug;
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::{fs, io};
+
+/// Well-known name of the freedesktop Secret Service on the session bus
+const SERVICE_NAME: &str = "org.freedesktop.secrets";
+const SERVICE_PATH: &str = "/org/freedesktop/secrets";
+const SERVICE_INTERFACE: &str = "org.freedesktop.Secret.Service";
+const ITEM_INTERFACE: &str = "org.freedesktop.Secret.Item";
+const SESSION_INTERFACE: &str = "org.freedesktop.Secret.Session";
+
+/// Timeout of a single call to the Secret Service, in milliseconds
+const CALL_TIMEOUT: i32 = 5000;
+
+/// Resolve the values of the secret args.
+/// The error names the arg, but never contains the value.
+///
+pub fn resolve_all(secrets: &[(String, Secret)]) -> io::Result<HashMap<String, String>> {
+    secrets
+        .iter()
+        .map(|(name, secret)| match resolve(secret) {
+            Ok(value) => Ok((name.clone(), value)),
+            Err(e) => {
+                let msg = format!("Failed to resolve arg '{}' from {}: {}", name, secret, e);
+                Err(io::Error::new(e.kind(), msg))
+            }
+        })
+        .collect()
+}
+
+fn resolve(secret: &Secret) -> io::Result<String> {
+    match secret {
+        Secret::File(path) => read_file(path),
+        Secret::Env(name) => {
+            std::env::var(name).map_err(|e| io::Error::new(ErrorKind::NotFound, e))
+        }
+        Secret::Service(attributes) => {
+            let connection =
+                gio::bus_get_sync(BusType::Session, Cancellable::NONE).map_err(io::Error::other)?;
+            lookup(&connection, Some(SERVICE_NAME), attributes)
+        }
+    }
+}
+
+/// Read the whole file except the trailing newline, which editors tend to add.
+///
+fn read_file(path: &Path) -> io::Result<String> {
+    let mut value = fs::read_to_string(path)?;
+    if value.ends_with('\n') {
+        value.pop();
+        if value.ends_with('\r') {
+            value.pop();
+        }
+    }
+    Ok(value)
+}
+
+/// Find the unlocked item with the attributes and read its secret
+/// in a plain session, which is fine for the local bus.
+///
+fn lookup(
+    connection: &DBusConnection,
+    service: Option<&str>,
+    attributes: &[(String, String)],
+) -> io::Result<String> {
+    let call = |path: &str, interface: &str, method: &str, parameters: Variant, reply: &str| {
+        connection
+            .call_sync(
+                service,
+                path,
+                interface,
+                method,
+                Some(&parameters),
+                Some(VariantTy::new(reply).unwrap()),
+                DBusCallFlags::NONE,
+                CALL_TIMEOUT,
+                Cancellable::NONE,
+            )
+            .map_err(io::Error::other)
+    };
+
+    let reply = call(
+        SERVICE_PATH,
+        SERVICE_INTERFACE,
+        "OpenSession",
+        ("plain", "".to_variant()).to_variant(),
+        "(vo)",
+    )?;
+    let (_, session) = reply.get::<(Variant, ObjectPath)>().unwrap();
+
+    let attributes: HashMap<&str, &str> = attributes
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let result = call(
+        SERVICE_PATH,
+        SERVICE_INTERFACE,
+        "SearchItems",
+        (attributes,).to_variant(),
+        "(aoao)",
+    )
+    .and_then(|reply| {
+        let (unlocked, locked) = reply.get::<(Vec<ObjectPath>, Vec<ObjectPath>)>().unwrap();
+        match (unlocked.first(), locked.is_empty()) {
+            (Some(item), _) => {
+                debug!("Reading the secret of {}", item.as_str());
+                let reply = call(
+                    item,
+                    ITEM_INTERFACE,
+                    "GetSecret",
+                    (session.clone(),).to_variant(),
+                    "((oayays))",
+                )?;
+                let ((_, _, value, _),) = reply
+                    .get::<((ObjectPath, Vec<u8>, Vec<u8>, String),)>()
+                    .unwrap();
+                String::from_utf8(value).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))
+            }
+            (None, false) => Err(io::Error::new(
+                ErrorKind::PermissionDenied,
+                "the item is locked, unlock the keyring first",
+            )),
+            (None, true) => Err(io::Error::new(ErrorKind::NotFound, "no such item")),
+        }
+    });
+
+    if let Err(e) = call(&session, SESSION_INTERFACE, "Close", ().to_variant(), "()") {
+        debug!("Failed to close the secret session: {}", e);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gtk::gio::prelude::*;
+    use gtk::gio::{DBusConnectionFlags, DBusMessage, DBusMessageType, DBusSendMessageFlags};
+    use std::io::Write;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+    use tempfile::NamedTempFile;
+
+    const SESSION: &str = "/org/freedesktop/secrets/session/1";
+    const ITEM: &str = "/org/freedesktop/secrets/collection/login/1";
+    const LOCKED_ITEM: &str = "/org/freedesktop/secrets/collection/locked/1";
+
+    fn path(path: &str) -> ObjectPath {
+        ObjectPath::try_from(path).unwrap()
+    }
+
+    fn connect(stream: UnixStream, flags: DBusConnectionFlags) -> DBusConnection {
+        let socket = unsafe { gio::Socket::from_fd(stream) }.unwrap();
+        let stream = socket.connection_factory_create_connection();
+        let guid = flags
+            .contains(DBusConnectionFlags::AUTHENTICATION_SERVER)
+            .then(gio::dbus_generate_guid);
+        DBusConnection::new_sync(&stream, guid.as_deref(), flags, None, Cancellable::NONE).unwrap()
+    }
+
+    /// Serve the mock Secret Service over a private connection,
+    /// with the unlocked item `service=db` and the locked one `service=vault`.
+    /// Returns the client connection and the service one, which must be kept.
+    ///
+    fn mock_service() -> (DBusConnection, DBusConnection) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = thread::spawn(move || {
+            let connection = connect(server, DBusConnectionFlags::AUTHENTICATION_SERVER);
+            connection.add_filter(|connection, message, incoming| {
+                if !incoming || message.message_type() != DBusMessageType::MethodCall {
+                    return Some(message.clone());
+                }
+                let reply = message.new_method_reply();
+                if let Some(body) = handle_call(message) {
+                    reply.set_body(&body);
+                }
+                let _ = connection.send_message(&reply, DBusSendMessageFlags::NONE);
+                None
+            });
+            connection
+        });
+        let client = connect(client, DBusConnectionFlags::AUTHENTICATION_CLIENT);
+        (client, server.join().unwrap())
+    }
+
+    fn handle_call(message: &DBusMessage) -> Option<Variant> {
+        match message.member()?.as_str() {
+            "OpenSession" => Some(("".to_variant(), path(SESSION)).to_variant()),
+            "SearchItems" => {
+                let body = message.body()?;
+                let (attributes,) = body.get::<(HashMap<String, String>,)>()?;
+                let (unlocked, locked) = match attributes.get("service").map(String::as_str) {
+                    Some("db") => (vec![path(ITEM)], vec![]),
+                    Some("vault") => (vec![], vec![path(LOCKED_ITEM)]),
+                    _ => (vec![], vec![]),
+                };
+                Some((unlocked, locked).to_variant())
+            }
+            "GetSecret" => {
+                let secret = (
+                    path(SESSION),
+                    Vec::<u8>::new(),
+                    b"s3cret".to_vec(),
+                    "text/plain",
+                );
+                Some((secret,).to_variant())
+            }
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn lookup_secret_service() {
+        let (connection, _service) = mock_service();
+        let attributes = [("service".to_string(), "db".to_string())];
+        assert_eq!(lookup(&connection, None, &attributes).unwrap(), "s3cret");
+
+        let attributes = [("service".to_string(), "vault".to_string())];
+        let res = lookup(&connection, None, &attributes);
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::PermissionDenied);
+
+        let attributes = [("service".to_string(), "none".to_string())];
+        let res = lookup(&connection, None, &attributes);
+        assert_eq!(res.err().unwrap().kind(), ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn resolve_file_and_env() -> io::Result<()> {
+        let mut file = NamedTempFile::new()?;
+        writeln!(file, "p@ss word")?;
+        std::env::set_var("PROGRAM_TRAY_TEST_SECRET", "from env");
+        let secrets = [
+            ("file".to_string(), Secret::File(file.path().to_path_buf())),
+            (
+                "env".to_string(),
+                Secret::Env("PROGRAM_TRAY_TEST_SECRET".to_string()),
+            ),
+        ];
+        let values = resolve_all(&secrets)?;
+        assert_eq!(values.get("file").unwrap(), "p@ss word");
+        assert_eq!(values.get("env").unwrap(), "from env");
+
+        let secrets = [(
+            "token".to_string(),
+            Secret::Env("PROGRAM_TRAY_TEST_MISSING".to_string()),
+        )];
+        let msg = resolve_all(&secrets).err().unwrap().to_string();
+        assert!(msg.starts_with("Failed to resolve arg 'token' from environment variable"));
+        Ok(())
+    }
+}
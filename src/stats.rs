@@ -0,0 +1,161 @@
+use crate::procfs;
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Resource usage of the running program together with its descendants
+///
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stats {
+    pub pid: u32,
+    pub uptime: Duration,
+    /// CPU usage since the previous sample, in percent of a single CPU
+    pub cpu: f64,
+    /// Resident memory, in bytes
+    pub rss: u64,
+    pub threads: u32,
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.uptime.as_secs();
+        write!(
+            f,
+            "PID {}, up {}:{:02}:{:02}, CPU {:.1}%, RSS {}, {} threads",
+            self.pid,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            self.cpu,
+            format_size(self.rss),
+            self.threads
+        )
+    }
+}
+
+/// Samples the usage of the program, the CPU one is measured between the samples.
+///
+pub struct Sampler {
+    pid: u32,
+    /// Time of the previous sample and the CPU time of the processes then, in ticks
+    last: (Instant, u64),
+    ticks_per_second: u64,
+    page_size: u64,
+}
+
+impl Sampler {
+    /// Start sampling the just started program, which has used no CPU time yet.
+    ///
+    pub fn new(pid: u32) -> Self {
+        let (ticks, page) = unsafe {
+            (
+                libc::sysconf(libc::_SC_CLK_TCK),
+                libc::sysconf(libc::_SC_PAGESIZE),
+            )
+        };
+        Sampler {
+            pid,
+            last: (Instant::now(), 0),
+            ticks_per_second: ticks.max(1) as u64,
+            page_size: page.max(1) as u64,
+        }
+    }
+
+    /// Sum the usage of the program and its living descendants.
+    /// Fails if the program itself has exited.
+    ///
+    pub fn sample(&mut self, uptime: Duration) -> io::Result<Stats> {
+        let mut total = procfs::read_usage(self.pid)?;
+        let descendants = procfs::find_descendants(self.pid)?;
+        for process in descendants.iter().filter(|process| !process.is_zombie()) {
+            // the descendant may exit meanwhile
+            if let Ok(usage) = procfs::read_usage(process.pid) {
+                total.cpu_ticks += usage.cpu_ticks;
+                total.threads += usage.threads;
+                total.rss_pages += usage.rss_pages;
+            }
+        }
+
+        let now = Instant::now();
+        let (last_time, last_ticks) = std::mem::replace(&mut self.last, (now, total.cpu_ticks));
+        // the CPU time of the exited descendants is gone from the sum
+        let ticks = total.cpu_ticks.saturating_sub(last_ticks);
+        let elapsed = now.duration_since(last_time).as_secs_f64();
+        let cpu = match elapsed > 0.0 {
+            true => ticks as f64 / self.ticks_per_second as f64 / elapsed * 100.0,
+            false => 0.0,
+        };
+        Ok(Stats {
+            pid: self.pid,
+            uptime,
+            cpu,
+            rss: total.rss_pages * self.page_size,
+            threads: total.threads,
+        })
+    }
+}
+
+/// Size in bytes with the binary unit, like `12.5 MiB`.
+///
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+    use std::thread::sleep;
+
+    #[test]
+    fn format_stats() {
+        let stats = Stats {
+            pid: 1234,
+            uptime: Duration::from_secs(3 * 3600 + 62),
+            cpu: 12.345,
+            rss: 13_107_200,
+            threads: 5,
+        };
+        assert_eq!(
+            stats.to_string(),
+            "PID 1234, up 3:01:02, CPU 12.3%, RSS 12.5 MiB, 5 threads"
+        );
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(3 << 30), "3.0 GiB");
+    }
+
+    #[test]
+    fn sample_busy_descendant() -> io::Result<()> {
+        // the shell itself only waits, the CPU is used by its child
+        let mut child = Command::new("sh")
+            .args(["-c", "sh -c 'while :; do :; done' & wait"])
+            .process_group(0)
+            .spawn()?;
+        let mut sampler = Sampler::new(child.id());
+        sleep(Duration::from_millis(500));
+        let stats = sampler.sample(Duration::from_secs(1));
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+        child.wait()?;
+
+        let stats = stats?;
+        assert_eq!(stats.pid, child.id());
+        assert!(stats.cpu > 10.0, "{}", stats);
+        assert!(stats.threads >= 2, "{}", stats);
+        assert!(stats.rss > 0);
+
+        assert!(sampler.sample(Duration::from_secs(2)).is_err());
+        Ok(())
+    }
+}
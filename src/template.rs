@@ -0,0 +1,283 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::ErrorKind;
+
+/// How the values are written into the template
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quoting {
+    /// As is, for the input and paths
+    Plain,
+    /// Quoted for the shell-like split of the command line, depending on the quotes around
+    Shell,
+}
+
+/// Placeholder of the template, starting with `$`
+///
+enum Placeholder<'a> {
+    /// `$$`, the escaped dollar
+    Dollar,
+    /// `$name`, `${name}` or `${name:-default}`
+    Var {
+        name: &'a str,
+        default: Option<&'a str>,
+    },
+    /// `$` followed by anything else, taken as is
+    Literal,
+}
+
+/// Replace the placeholders of the template: `$name`, `${name}` and `${name:-default}`
+/// with the value of the first map defining the name or else of the environment variable,
+/// and `$$` with `$`. The default is taken for the undefined or empty value.
+/// Fails on the undefined placeholder without a default.
+///
+pub fn replace_args(
+    template: &str,
+    vars: &[&HashMap<String, String>],
+    quoting: Quoting,
+) -> io::Result<String> {
+    replace(template, vars, None, quoting)
+}
+
+/// Replace the placeholders as `replace_args` does, except the deferred ones.
+/// These placeholders and the `$$` escapes are left for the final `replace_args`,
+/// which is why the dollars of the substituted values are escaped.
+///
+pub fn replace_known_args(
+    template: &str,
+    vars: &[&HashMap<String, String>],
+    deferred: &[&str],
+    quoting: Quoting,
+) -> io::Result<String> {
+    replace(template, vars, Some(deferred), quoting)
+}
+
+fn replace(
+    template: &str,
+    vars: &[&HashMap<String, String>],
+    deferred: Option<&[&str]>,
+    quoting: Quoting,
+) -> io::Result<String> {
+    let mut result = String::with_capacity(template.len());
+    let mut quote = None; // the open quote of the command line
+    let mut rest = template;
+    while let Some(c) = rest.chars().next() {
+        if c == '$' {
+            let (placeholder, len) = parse_placeholder(rest)?;
+            match placeholder {
+                Placeholder::Dollar if deferred.is_some() => result.push_str("$$"),
+                Placeholder::Dollar | Placeholder::Literal => result.push('$'),
+                Placeholder::Var { name, .. }
+                    if deferred.is_some_and(|deferred| deferred.contains(&name)) =>
+                {
+                    result.push_str(&rest[..len]);
+                }
+                Placeholder::Var { name, default } => {
+                    let value = vars
+                        .iter()
+                        .find_map(|vars| vars.get(name).cloned())
+                        .or_else(|| std::env::var(name).ok());
+                    let value = match (value, default) {
+                        (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+                        (Some(value), _) => value,
+                        (None, Some(default)) => default.to_string(),
+                        (None, None) => {
+                            let msg = format!("undefined placeholder '{}'", &rest[..len]);
+                            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+                        }
+                    };
+                    let value = quote_value(&value, quoting, quote);
+                    match deferred {
+                        Some(_) => result.push_str(&value.replace('$', "$$")),
+                        None => result.push_str(&value),
+                    }
+                }
+            }
+            rest = &rest[len..];
+            continue;
+        }
+
+        let mut len = c.len_utf8();
+        if quoting == Quoting::Shell {
+            match (c, quote) {
+                // the escaped character is taken as is, but not in single quotes
+                ('\\', None | Some('"')) => {
+                    len += rest[len..].chars().next().map_or(0, char::len_utf8);
+                }
+                ('\'' | '"', None) => quote = Some(c),
+                (c, Some(open)) if c == open => quote = None,
+                _ => {}
+            }
+        }
+        result.push_str(&rest[..len]);
+        rest = &rest[len..];
+    }
+    Ok(result)
+}
+
+/// Split the command line into the program and its arguments like a shell does.
+/// Fails on the unclosed quote or the trailing backslash.
+///
+pub fn split_command(line: &str) -> io::Result<Vec<String>> {
+    shlex::split(line).ok_or_else(|| {
+        let msg = format!("unclosed quote or trailing backslash in '{}'", line);
+        io::Error::new(ErrorKind::InvalidInput, msg)
+    })
+}
+
+/// Parse the placeholder at the start of the text, returning it with its length.
+///
+fn parse_placeholder(text: &str) -> io::Result<(Placeholder<'_>, usize)> {
+    let body = &text[1..];
+    if body.starts_with('$') {
+        return Ok((Placeholder::Dollar, 2));
+    }
+    if let Some(braced) = body.strip_prefix('{') {
+        let Some(end) = braced.find('}') else {
+            let msg = format!("missing closing brace of '{}'", text);
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        };
+        let (name, default) = match braced[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&braced[..end], None),
+        };
+        if name.is_empty() || !name.chars().all(is_name_char) {
+            let msg = format!("invalid placeholder '{}'", &text[..end + 3]);
+            return Err(io::Error::new(ErrorKind::InvalidInput, msg));
+        }
+        return Ok((Placeholder::Var { name, default }, end + 3));
+    }
+    match body.find(|c| !is_name_char(c)).unwrap_or(body.len()) {
+        0 => Ok((Placeholder::Literal, 1)),
+        len => Ok((
+            Placeholder::Var {
+                name: &body[..len],
+                default: None,
+            },
+            len + 1,
+        )),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+/// Quote the value to be taken as a single argument of the command line,
+/// in the quotes it is placed in. The result is also fine for a shell,
+/// which expands `$` and `` ` `` in double quotes, so they are put in single ones.
+///
+fn quote_value(value: &str, quoting: Quoting, quote: Option<char>) -> String {
+    match (quoting, quote) {
+        (Quoting::Plain, _) => value.to_string(),
+        (Quoting::Shell, Some('\'')) => value.replace('\'', r"'\''"),
+        (Quoting::Shell, Some(_)) => value
+            .replace('\\', r"\\")
+            .replace('"', "\\\"")
+            .replace('$', r#""'$'""#)
+            .replace('`', r#""'`'""#),
+        (Quoting::Shell, None) => {
+            let safe = |c: char| c.is_ascii_alphanumeric() || "_-.,:/@%+=".contains(c);
+            match !value.is_empty() && value.chars().all(safe) {
+                true => value.to_string(),
+                false => format!("'{}'", value.replace('\'', r"'\''")),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shlex::split;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn replace_placeholders() -> io::Result<()> {
+        let args = vars(&[("user", "user1"), ("empty", "")]);
+        let env = vars(&[("user", "env user"), ("port", "8080")]);
+        std::env::set_var("PROGRAM_TRAY_TEST_HOST", "localhost");
+        let replace = |template| replace_args(template, &[&args, &env], Quoting::Plain);
+
+        assert_eq!(replace("$user:${port}")?, "user1:8080");
+        assert_eq!(
+            replace("${PROGRAM_TRAY_TEST_HOST}/$user")?,
+            "localhost/user1"
+        );
+        assert_eq!(replace("${empty:-none} ${other:-a b}")?, "none a b");
+        assert_eq!(replace("$$user costs 5$ or $")?, "$user costs 5$ or $");
+        assert_eq!(
+            replace("$undefined").err().unwrap().to_string(),
+            "undefined placeholder '$undefined'"
+        );
+        assert_eq!(
+            replace("${user").err().unwrap().to_string(),
+            "missing closing brace of '${user'"
+        );
+        assert_eq!(
+            replace("${us-er}").err().unwrap().to_string(),
+            "invalid placeholder '${us-er}'"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn quote_values() -> io::Result<()> {
+        let args = vars(&[("name", "it's \"a\" \\ $HOME"), ("simple", "a.b")]);
+        let replace = |template| replace_args(template, &[&args], Quoting::Shell);
+
+        let value = "it's \"a\" \\ $HOME";
+        assert_eq!(
+            split(&replace("echo $name $simple")?).unwrap(),
+            ["echo", value, "a.b"]
+        );
+        assert_eq!(split(&replace("echo '$name'")?).unwrap(), ["echo", value]);
+        assert_eq!(split(&replace("echo \"$name\"")?).unwrap(), ["echo", value]);
+        assert_eq!(
+            split(&replace("sh -c 'echo \"$name\"' \\'$simple")?).unwrap(),
+            ["sh", "-c", "echo \"it's \"a\" \\ $HOME\"", "'a.b"]
+        );
+        assert_eq!(split(&replace("echo ${missing:-}")?).unwrap(), ["echo", ""]);
+        Ok(())
+    }
+
+    #[test]
+    fn quote_values_for_shell() -> io::Result<()> {
+        let value = "it's \"a\" \\ $HOME `id`";
+        let args = vars(&[("name", value)]);
+        for template in [
+            "printf %s $name",
+            "printf %s '$name'",
+            "printf %s \"$name\"",
+        ] {
+            let line = replace_args(template, &[&args], Quoting::Shell)?;
+            let output = std::process::Command::new("sh")
+                .args(["-c", &line])
+                .output()?;
+            assert_eq!(String::from_utf8_lossy(&output.stdout), value, "{}", line);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn defer_placeholders() -> io::Result<()> {
+        let args = vars(&[("user", "$user's")]);
+        let template = "cmd $user ${otp:-none} $$otp '$password'";
+        let partial = replace_known_args(template, &[&args], &["otp", "password"], Quoting::Shell)?;
+        assert_eq!(partial, "cmd '$$user'\\''s' ${otp:-none} $$otp '$password'");
+
+        let args = vars(&[("otp", "1 2"), ("password", "p'w")]);
+        let result = replace_args(&partial, &[&args], Quoting::Shell)?;
+        assert_eq!(
+            split(&result).unwrap(),
+            ["cmd", "$user's", "1 2", "$otp", "p'w"]
+        );
+        Ok(())
+    }
+}
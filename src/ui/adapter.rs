@@ -1,48 +1,147 @@
-use crate::launcher::Launcher;
-use crate::ui::component::{Component, MenuAction, Message};
+use crate::config::Program;
+use crate::group::Group;
+use crate::ui::component::{Component, MenuAction, Message, TerminalAction};
+use crate::ui::prompt;
 use gtk::glib::Sender;
+use log::error;
 use std::cell::RefCell;
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct LauncherAdapter {
-    delegate: Rc<RefCell<Launcher>>,
+    delegate: Rc<RefCell<Group>>,
+    titles: Vec<String>,
 }
 
 impl LauncherAdapter {
-    pub fn new(launcher: &Rc<RefCell<Launcher>>) -> Self {
+    pub fn new(group: &Rc<RefCell<Group>>, programs: &[Program]) -> Self {
         Self {
-            delegate: Rc::clone(launcher),
+            delegate: Rc::clone(group),
+            titles: programs.iter().map(|p| p.get_title().to_string()).collect(),
+        }
+    }
+
+    /// Ask for the prompted args of the programs which are going to be started.
+    /// Returns false if the user cancelled the start.
+    ///
+    fn ask_args(&self, group: &Group, indexes: &[usize]) -> bool {
+        let indexes: Vec<usize> = indexes
+            .iter()
+            .copied()
+            .filter(|index| !group.is_running(*index))
+            .filter(|index| !group.get(*index).get_prompts().is_empty())
+            .collect();
+        if indexes.is_empty() {
+            return true;
+        }
+        let programs: Vec<(&str, _)> = indexes
+            .iter()
+            .map(|index| {
+                (
+                    self.titles[*index].as_str(),
+                    group.get(*index).get_prompts(),
+                )
+            })
+            .collect();
+        match prompt::ask_args(&programs) {
+            Some(values) => {
+                for (index, values) in indexes.iter().zip(values) {
+                    group.get(*index).set_prompted_args(values);
+                }
+                true
+            }
+            None => false,
         }
     }
 }
 
 impl Component for LauncherAdapter {
     fn start(&mut self, tx: &Sender<Message>) {
-        let mut delegate = self.delegate.borrow_mut();
-        let ctx = tx.clone();
-        delegate.set_output_handler(move |text| {
-            let _ = ctx.send(Message::ProgramOutput(text));
-        });
-        let ctx = tx.clone();
-        delegate.set_status_handler(move |status| {
-            let _ = ctx.send(Message::ProgramStopped(status));
-        })
+        let mut group = self.delegate.borrow_mut();
+        for index in 0..group.count() {
+            let delegate = group.get_mut(index);
+            let ctx = tx.clone();
+            delegate.set_start_handler(move |_| {
+                let _ = ctx.send(Message::ProgramStarted(index));
+            });
+            let ctx = tx.clone();
+            delegate.set_output_handler(move |line| {
+                let _ = ctx.send(Message::ProgramOutput(index, line));
+            });
+            let ctx = tx.clone();
+            delegate.set_ready_handler(move |event| {
+                let _ = ctx.send(Message::ProgramReady(index, event));
+            });
+            let ctx = tx.clone();
+            delegate.set_stop_handler(move |event| {
+                let _ = ctx.send(Message::ProgramStopping(index, event));
+            });
+            let ctx = tx.clone();
+            delegate.set_status_handler(move |status| {
+                let _ = ctx.send(Message::ProgramStopped(index, status));
+            });
+            let ctx = tx.clone();
+            delegate.set_restart_handler(move |event| {
+                let _ = ctx.send(Message::ProgramRestart(index, event));
+            });
+            let ctx = tx.clone();
+            delegate.set_health_handler(move |event| {
+                let _ = ctx.send(Message::ProgramHealth(index, event));
+            });
+            let ctx = tx.clone();
+            delegate.set_hook_handler(move |event| {
+                let _ = ctx.send(Message::ProgramHook(index, event));
+            });
+            let ctx = tx.clone();
+            delegate.set_stats_handler(move |stats| {
+                let _ = ctx.send(Message::ProgramStats(index, stats));
+            });
+        }
     }
 
     fn on_message_received(&mut self, msg: &Message) {
         match msg {
-            Message::TrayMenu(action) => match action {
-                MenuAction::RUN => {
-                    let mut launcher = self.delegate.borrow_mut();
-                    if !launcher.is_running() {
-                        launcher.start().unwrap();
-                    } else {
-                        launcher.stop_async();
+            Message::TrayMenu(action) => {
+                let group = self.delegate.borrow();
+                match action {
+                    MenuAction::RUN(index) => {
+                        if !group.is_running(*index) {
+                            if self.ask_args(&group, &group.start_order(*index)) {
+                                group.start_async(*index);
+                            }
+                        } else {
+                            group.stop_async(*index);
+                        }
+                    }
+                    MenuAction::START_ALL => {
+                        let indexes: Vec<usize> = (0..group.count()).collect();
+                        if self.ask_args(&group, &indexes) {
+                            group.start_all_async();
+                        }
                     }
+                    MenuAction::STOP_ALL => group.stop_all_async(),
+                    _ => {}
+                }
+            }
+            Message::Terminal(index, TerminalAction::RESIZE(columns, rows)) => {
+                let group = self.delegate.borrow();
+                group.get(*index).set_window_size(*columns, *rows);
+            }
+            Message::Terminal(index, TerminalAction::INPUT(text)) => {
+                let group = self.delegate.borrow();
+                if let Err(e) = group.get(*index).write_input(text) {
+                    error!(
+                        "Failed to write the input of {}: {}",
+                        self.titles[*index], e
+                    );
+                }
+            }
+            Message::Terminal(index, TerminalAction::EOF) => {
+                let group = self.delegate.borrow();
+                if let Err(e) = group.get(*index).send_eof() {
+                    error!("Failed to end the input of {}: {}", self.titles[*index], e);
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     }
@@ -1,5 +1,5 @@
 use crate::config::Program;
-use crate::launcher::Launcher;
+use crate::group::Group;
 use crate::ui::adapter::LauncherAdapter;
 use crate::ui::component::*;
 use crate::ui::icons::Icons;
@@ -14,18 +14,22 @@ use std::rc::Rc;
 ///
 pub struct App {
     tray: Tray,
-    terminal: Terminal,
+    terminals: Vec<Terminal>,
     launcher: LauncherAdapter,
 }
 
 impl App {
-    pub fn new(program: &Program, icons: &Icons, launcher: &Rc<RefCell<Launcher>>) -> Self {
-        let tray = Tray::new(program, icons);
-        let terminal = Terminal::new(program);
-        let launcher = LauncherAdapter::new(launcher); // wtf???
+    pub fn new(programs: &[Program], icons: &Icons, group: &Rc<RefCell<Group>>) -> Self {
+        let tray = Tray::new(programs, icons);
+        let terminals = programs
+            .iter()
+            .enumerate()
+            .map(|(index, program)| Terminal::new(index, program))
+            .collect();
+        let launcher = LauncherAdapter::new(group, programs); // wtf???
         Self {
             tray,
-            terminal,
+            terminals,
             launcher,
         }
     }
@@ -33,11 +37,11 @@ impl App {
     pub fn start(&mut self) {
         let (tx, rx) = glib::MainContext::channel(Priority::DEFAULT);
 
-        let mut handlers: Vec<Box<dyn Component>> = vec![
-            Box::new(self.tray.clone()),
-            Box::new(self.terminal.clone()),
-            Box::new(self.launcher.clone()),
-        ];
+        let mut handlers: Vec<Box<dyn Component>> = vec![Box::new(self.tray.clone())];
+        for terminal in self.terminals.iter() {
+            handlers.push(Box::new(terminal.clone()));
+        }
+        handlers.push(Box::new(self.launcher.clone()));
 
         handlers.iter_mut().for_each(|h| h.start(&tx));
 
@@ -1,23 +1,47 @@
+use crate::health::HealthEvent;
+use crate::launcher::{HookEvent, ReadyEvent, RestartEvent, StopEvent};
+use crate::output::OutputLine;
+use crate::stats::Stats;
 use gtk::glib::Sender;
 use muda::MenuId;
 use std::process::ExitStatus;
 
+/// Actions of the tray menu, the program ones refer to the program index
+///
+#[allow(non_camel_case_types)]
 pub enum MenuAction {
     UNKNOWN(MenuId),
-    RUN,
-    VISIBILITY,
+    RUN(usize),
+    VISIBILITY(usize),
+    START_ALL,
+    STOP_ALL,
     QUIT,
 }
 
 pub enum TerminalAction {
     HIDE,
+    /// The text area got the size in characters: columns and rows
+    RESIZE(u16, u16),
+    /// The text is entered for the input of the program
+    INPUT(String),
+    /// The end of the input is requested
+    EOF,
 }
 
+/// Events of the application, the program ones carry the program index
+///
 pub enum Message {
     TrayMenu(MenuAction),
-    Terminal(TerminalAction),
-    ProgramOutput(String),
-    ProgramStopped(ExitStatus),
+    Terminal(usize, TerminalAction),
+    ProgramStarted(usize),
+    ProgramOutput(usize, OutputLine),
+    ProgramReady(usize, ReadyEvent),
+    ProgramStopping(usize, StopEvent),
+    ProgramStopped(usize, ExitStatus),
+    ProgramRestart(usize, RestartEvent),
+    ProgramHealth(usize, HealthEvent),
+    ProgramHook(usize, HookEvent),
+    ProgramStats(usize, Stats),
 }
 
 pub trait Component {
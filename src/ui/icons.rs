@@ -7,21 +7,37 @@ use tray_icon::Icon;
 
 const ICON_ON: &[u8] = include_bytes!("../../resources/on.png");
 const ICON_OFF: &[u8] = include_bytes!("../../resources/off.png");
+const ICON_STARTING: &[u8] = include_bytes!("../../resources/starting.png");
+const ICON_UNHEALTHY: &[u8] = include_bytes!("../../resources/unhealthy.png");
 
 #[derive(Clone)]
 pub struct Icons {
     pub on: Icon,
     pub off: Icon,
+    pub starting: Icon,
+    pub unhealthy: Icon,
 }
 
 pub fn load_icons(program: &Program) -> io::Result<Icons> {
-    load_icons0(program.get_icon_on_path(), program.get_icon_off_path())
+    load_icons0(
+        program.get_icon_on_path(),
+        program.get_icon_off_path(),
+        program.get_icon_starting_path(),
+        program.get_icon_unhealthy_path(),
+    )
 }
 
-fn load_icons0(on_icon_path: Option<&str>, off_icon_path: Option<&str>) -> io::Result<Icons> {
+fn load_icons0(
+    on_icon_path: Option<&str>,
+    off_icon_path: Option<&str>,
+    starting_icon_path: Option<&str>,
+    unhealthy_icon_path: Option<&str>,
+) -> io::Result<Icons> {
     Ok(Icons {
         on: load_icon(on_icon_path, ICON_ON)?,
         off: load_icon(off_icon_path, ICON_OFF)?,
+        starting: load_icon(starting_icon_path, ICON_STARTING)?,
+        unhealthy: load_icon(unhealthy_icon_path, ICON_UNHEALTHY)?,
     })
 }
 
@@ -53,15 +69,18 @@ mod tests {
 
     #[test]
     fn load_defaults() -> io::Result<()> {
-        let _ = load_icons0(None, None)?;
+        let _ = load_icons0(None, None, None, None)?;
         Ok(())
     }
 
     #[test]
     fn load_invalid_path() {
-        let res = load_icons0(None, Some("invalid.png"));
+        let res = load_icons0(None, Some("invalid.png"), None, None);
         assert!(res.is_err());
         assert_eq!(res.err().unwrap().kind(), io::ErrorKind::NotFound);
+
+        let res = load_icons0(None, None, None, Some("invalid.png"));
+        assert_eq!(res.err().unwrap().kind(), io::ErrorKind::NotFound);
     }
 
     #[test]
@@ -70,7 +89,7 @@ mod tests {
         let path = temp_file.path().to_str().unwrap();
         temp_file.as_file().write_all(br#"garbage"#)?;
 
-        let res = load_icons0(None, Some(path));
+        let res = load_icons0(None, Some(path), None, None);
         assert!(res.is_err());
         assert_eq!(res.err().unwrap().kind(), io::ErrorKind::InvalidData);
         Ok(())
@@ -82,7 +101,7 @@ mod tests {
         let path = temp_file.path().to_str().unwrap();
         temp_file.as_file().write_all(ICON_ON)?;
 
-        let _ = load_icons0(Some(path), Some(path))?;
+        let _ = load_icons0(Some(path), Some(path), Some(path), Some(path))?;
         Ok(())
     }
 }
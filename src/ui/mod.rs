@@ -2,5 +2,6 @@ mod adapter;
 pub mod app;
 mod component;
 pub mod icons;
+mod prompt;
 mod terminal;
 mod tray;
@@ -0,0 +1,79 @@
+use crate::config::Prompt;
+use gtk::prelude::*;
+use gtk::{Dialog, DialogFlags, Entry, Grid, InputPurpose, Label, ResponseType, Window};
+use std::collections::HashMap;
+
+/// Ask the user for the prompted args of the programs to be started.
+/// Returns the values for each program, or `None` if the start is cancelled.
+///
+pub fn ask_args(programs: &[(&str, &[Prompt])]) -> Option<Vec<HashMap<String, String>>> {
+    let title = match programs {
+        [(title, _)] => format!("Start {}", title),
+        _ => "Start programs".to_string(),
+    };
+    let dialog = Dialog::with_buttons(
+        Some(&title),
+        None::<&Window>,
+        DialogFlags::MODAL,
+        &[
+            ("Cancel", ResponseType::Cancel),
+            ("Start", ResponseType::Ok),
+        ],
+    );
+    dialog.set_default_response(ResponseType::Ok);
+
+    let grid = Grid::new();
+    grid.set_row_spacing(5);
+    grid.set_column_spacing(10);
+    grid.set_margin_start(10);
+    grid.set_margin_end(10);
+    grid.set_margin_top(10);
+    grid.set_margin_bottom(10);
+
+    let mut row = 0;
+    let mut entries: Vec<Vec<(String, Entry)>> = Vec::new();
+    for (title, prompts) in programs {
+        // Several programs are told apart by their titles
+        if programs.len() > 1 {
+            let label = Label::new(None);
+            label.set_markup(&format!("<b>{}</b>", gtk::glib::markup_escape_text(title)));
+            label.set_halign(gtk::Align::Start);
+            grid.attach(&label, 0, row, 2, 1);
+            row += 1;
+        }
+        let mut program_entries = Vec::new();
+        for prompt in prompts.iter() {
+            let label = Label::new(Some(prompt.get_label()));
+            label.set_halign(gtk::Align::End);
+            let entry = Entry::new();
+            entry.set_hexpand(true);
+            entry.set_activates_default(true);
+            if prompt.is_hidden() {
+                entry.set_visibility(false);
+                entry.set_input_purpose(InputPurpose::Password);
+            }
+            grid.attach(&label, 0, row, 1, 1);
+            grid.attach(&entry, 1, row, 1, 1);
+            row += 1;
+            program_entries.push((prompt.get_name().to_string(), entry));
+        }
+        entries.push(program_entries);
+    }
+    dialog.content_area().pack_start(&grid, true, true, 0);
+    dialog.show_all();
+
+    // Run the dialog and collect the values before it is destroyed
+    let response = dialog.run();
+    let values = entries
+        .iter()
+        .map(|program_entries| {
+            program_entries
+                .iter()
+                .map(|(name, entry)| (name.clone(), entry.text().to_string()))
+                .collect()
+        })
+        .collect();
+    dialog.close();
+
+    (response == ResponseType::Ok).then_some(values)
+}
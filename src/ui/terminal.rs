@@ -1,18 +1,43 @@
-use crate::config::Program;
+use crate::config::{Hook, Program};
+use crate::launcher::{HookEvent, RestartEvent, StopEvent};
+use crate::output::{OutputLine, Stream};
 use crate::ui::component::{Component, MenuAction, Message, TerminalAction};
-use gtk::glib::{Propagation, Sender};
+use gtk::gdk::keys::constants as keys;
+use gtk::glib::{DateTime, Propagation, Sender};
 use gtk::prelude::*;
-use gtk::{Button, ButtonsType, DialogFlags, MessageType, TextBuffer, TextView, Window};
+use gtk::{pango, CheckButton, Entry, InputPurpose, ScrolledWindow};
+use gtk::{Button, ButtonsType, DialogFlags, MessageType, Statusbar, TextBuffer, TextTag};
+use gtk::{TextView, Window};
+use std::cell::{Cell, RefCell};
 use std::process::ExitStatus;
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 const MARK_END: &str = "end";
+const TAG_STDERR: &str = "stderr";
+const TAG_TIMESTAMP: &str = "timestamp";
+const TAG_INPUT: &str = "input";
+const CONTEXT_STATS: &str = "stats";
+
+/// Width of the `HH:MM:SS ` timestamp of the output line, in characters
+const TIMESTAMP_WIDTH: i32 = 9;
 
 #[derive(Clone)]
 pub struct Terminal {
+    index: usize, // of the program
     window: Window,
     button: Button,
     buffer: TextBuffer,
     text_view: TextView,
+    scrolled_window: ScrolledWindow,
+    input_box: gtk::Box,
+    entry: Entry,
+    hide_button: CheckButton,
+    eof_button: Button,
+    /// Resource usage of the running program
+    statusbar: Statusbar,
+    /// Limits applied to the program, shown at the top of its output
+    limits: String,
     is_program_running: bool,
 }
 
@@ -20,20 +45,39 @@ impl Component for Terminal {
     fn start(&mut self, tx: &Sender<Message>) {
         self.connect_delete_event();
         self.connect_close_event(tx);
+        self.connect_resize_event(tx);
+        self.connect_input_event(tx);
     }
 
     fn on_message_received(&mut self, msg: &Message) {
         match msg {
             Message::TrayMenu(action) => self.on_tray_menu_selected(action),
-            Message::ProgramStopped(status) => self.on_program_stopped(status),
-            Message::ProgramOutput(text) => self.add_string(text),
-            Message::Terminal(_) => {}
+            Message::ProgramStarted(i) if *i == self.index => self.on_program_started(),
+            Message::ProgramStopping(i, event) if *i == self.index => {
+                self.on_program_stopping(event)
+            }
+            Message::ProgramStopped(i, status) if *i == self.index => {
+                self.on_program_stopped(status)
+            }
+            Message::ProgramOutput(i, line) if *i == self.index => self.add_line(line),
+            Message::ProgramReady(i, event) if *i == self.index => {
+                self.add_string(&format!("{}\n", event))
+            }
+            Message::ProgramRestart(i, event) if *i == self.index => self.on_program_restart(event),
+            Message::ProgramHealth(i, event) if *i == self.index => {
+                self.add_string(&format!("{}\n", event))
+            }
+            Message::ProgramHook(i, event) if *i == self.index => self.on_program_hook(event),
+            Message::ProgramStats(i, stats) if *i == self.index && self.is_program_running => {
+                self.set_stats(Some(&stats.to_string()))
+            }
+            _ => {}
         }
     }
 }
 
 impl Terminal {
-    pub fn new(program: &Program) -> Terminal {
+    pub fn new(index: usize, program: &Program) -> Terminal {
         // Create the main window (hidden by default)
         let window = Window::new(gtk::WindowType::Toplevel);
         window.set_title(program.get_title());
@@ -46,13 +90,29 @@ impl Terminal {
         let text_view = TextView::new();
         text_view.set_editable(false);
         text_view.set_cursor_visible(false);
+        // the program on the terminal aligns its output by columns
+        text_view.set_monospace(program.need_pty());
 
         // Add the terminal to a ScrolledWindow for scrolling
-        let scrolled_window = gtk::ScrolledWindow::builder()
+        let scrolled_window = ScrolledWindow::builder()
             .child(&text_view)
             .visible(true)
             .build();
 
+        // Create the input line of the program with its actions
+        let entry = Entry::new();
+        entry.set_hexpand(true);
+        entry.set_placeholder_text(Some("Input of the program"));
+        let hide_button = CheckButton::with_label("Hide typed text");
+        let eof_button = Button::with_label("Send EOF");
+        let input_box = gtk::Box::new(gtk::Orientation::Horizontal, 5);
+        input_box.set_margin_start(10);
+        input_box.set_margin_end(10);
+        input_box.pack_start(&entry, true, true, 0);
+        input_box.pack_start(&hide_button, false, false, 0);
+        input_box.pack_start(&eof_button, false, false, 0);
+        input_box.set_sensitive(false);
+
         // Create a Close Button
         let button = Button::with_label("Close");
         button.set_margin_start(10);
@@ -61,9 +121,13 @@ impl Terminal {
         button.set_margin_bottom(5);
         button.set_halign(gtk::Align::End);
 
+        let statusbar = Statusbar::new();
+
         // Add widgets to the vertical box
         vbox.pack_start(&scrolled_window, true, true, 0); // Expand Terminal
-        vbox.pack_start(&button, false, false, 0); // Place button at the bottom
+        vbox.pack_start(&input_box, false, false, 0);
+        vbox.pack_start(&button, false, false, 0);
+        vbox.pack_start(&statusbar, false, false, 0); // Place status at the bottom
 
         // Add the vertical box to the main window
         window.add(&vbox);
@@ -72,24 +136,57 @@ impl Terminal {
         let end_iter = buffer.end_iter();
         buffer.create_mark(Some(MARK_END), &end_iter, false);
 
+        let tags = buffer.tag_table().expect("Failed to get tag table");
+        let tag_stderr = TextTag::new(Some(TAG_STDERR));
+        tag_stderr.set_foreground(Some("red"));
+        tags.add(&tag_stderr);
+        let tag_timestamp = TextTag::new(Some(TAG_TIMESTAMP));
+        tag_timestamp.set_foreground(Some("gray"));
+        tags.add(&tag_timestamp);
+        let tag_input = TextTag::new(Some(TAG_INPUT));
+        tag_input.set_foreground(Some("blue"));
+        tags.add(&tag_input);
+
         Self {
+            index,
             window,
             button,
             buffer,
             text_view,
+            scrolled_window,
+            input_box,
+            entry,
+            hide_button,
+            eof_button,
+            statusbar,
+            limits: program.get_limits().to_string(),
             is_program_running: false,
         }
     }
 
+    /// The input is only accepted while the program runs.
+    ///
+    fn set_program_running(&mut self, running: bool) {
+        self.is_program_running = running;
+        self.input_box.set_sensitive(running);
+        if !running {
+            self.set_stats(None);
+        }
+    }
+
+    /// Show the resource usage in the status bar, or clear it once the program stops.
+    ///
+    fn set_stats(&self, stats: Option<&str>) {
+        let context = self.statusbar.context_id(CONTEXT_STATS);
+        self.statusbar.remove_all(context);
+        if let Some(stats) = stats {
+            self.statusbar.push(context, stats);
+        }
+    }
+
     fn on_tray_menu_selected(&mut self, action: &MenuAction) {
         match action {
-            MenuAction::RUN => {
-                if !self.is_program_running {
-                    self.clear();
-                    self.is_program_running = true;
-                }
-            }
-            MenuAction::VISIBILITY => {
+            MenuAction::VISIBILITY(index) if *index == self.index => {
                 if self.window.get_visible() {
                     self.window.hide();
                 } else {
@@ -100,15 +197,92 @@ impl Terminal {
         }
     }
 
+    fn on_program_started(&mut self) {
+        if !self.is_program_running {
+            self.clear();
+            if !self.limits.is_empty() {
+                self.add_string(&format!("Limits: {}\n", self.limits));
+            }
+            self.set_program_running(true);
+        }
+    }
+
+    fn on_program_stopping(&mut self, event: &StopEvent) {
+        let msg = match event {
+            StopEvent::Signalled(_) => format!("\n{}\n", event),
+            StopEvent::Escalated { .. } => format!("{}\n", event),
+        };
+        self.add_string(&msg);
+    }
+
     fn on_program_stopped(&mut self, status: &ExitStatus) {
         let msg = format!("Program stopped with status {}", status);
         self.add_string(&msg.to_string());
-        self.is_program_running = false;
+        self.set_program_running(false);
+    }
+
+    fn on_program_restart(&mut self, event: &RestartEvent) {
+        let msg = match event {
+            RestartEvent::Scheduled { .. } => {
+                self.set_program_running(true);
+                format!("\n{}\n", event)
+            }
+            RestartEvent::Restarted { .. } => format!("{}\n", event),
+            RestartEvent::Cancelled | RestartEvent::GaveUp { .. } => {
+                self.set_program_running(false);
+                format!("{}\n", event)
+            }
+        };
+        self.add_string(&msg);
+    }
+
+    fn on_program_hook(&mut self, event: &HookEvent) {
+        match event {
+            // the output of the pre_start hook belongs to the new run
+            HookEvent::Started {
+                hook: Hook::PreStart,
+                ..
+            } => self.on_program_started(),
+            HookEvent::Failed {
+                hook: Hook::PreStart,
+                ..
+            } => self.set_program_running(false),
+            _ => {}
+        }
+        self.add_string(&format!("{}\n", event));
     }
 
     pub fn add_string(&self, str: &String) {
+        self.insert(str, None);
+        self.scroll_to_end();
+    }
+
+    /// Add the program output line, highlighting the error stream.
+    ///
+    pub fn add_line(&self, line: &OutputLine) {
+        let time = format_time(line.timestamp);
+        self.insert(&format!("{} ", time), Some(TAG_TIMESTAMP));
+        let tag = match line.stream {
+            Stream::Stdout => None,
+            Stream::Stderr => Some(TAG_STDERR),
+        };
+        self.insert(&format!("{}\n", line.text), tag);
+        self.scroll_to_end();
+    }
+
+    fn insert(&self, text: &str, tag: Option<&str>) {
+        let offset = self.buffer.char_count();
         let mut end = self.buffer.end_iter();
-        self.buffer.insert(&mut end, &str);
+        self.buffer.insert(&mut end, text);
+        if let Some(tag) = tag {
+            let start = self.buffer.iter_at_offset(offset);
+            self.buffer
+                .apply_tag_by_name(tag, &start, &self.buffer.end_iter());
+        }
+    }
+
+    fn scroll_to_end(&self) {
+        let end = self.buffer.end_iter();
         self.buffer.move_mark_by_name(MARK_END, &end);
         let mark = &self
             .buffer
@@ -146,11 +320,153 @@ impl Terminal {
     }
 
     fn connect_close_event(&self, tx: &Sender<Message>) {
+        let index = self.index;
         let window = self.window.clone();
         let tx = tx.clone();
         self.button.connect_clicked(move |_| {
             window.hide();
-            let _ = tx.send(Message::Terminal(TerminalAction::HIDE));
+            let _ = tx.send(Message::Terminal(index, TerminalAction::HIDE));
+        });
+    }
+
+    /// Send the entered lines to the program, the hidden ones are neither shown
+    /// nor kept in the history, which is browsed with the Up and Down keys.
+    ///
+    fn connect_input_event(&self, tx: &Sender<Message>) {
+        let index = self.index;
+        let history = Rc::new(RefCell::new(History::default()));
+
+        let terminal = self.clone();
+        let history_clone = Rc::clone(&history);
+        let ctx = tx.clone();
+        self.entry.connect_activate(move |entry| {
+            let text = entry.text().to_string();
+            if !terminal.hide_button.is_active() {
+                terminal.insert(&format!("{}\n", text), Some(TAG_INPUT));
+                terminal.scroll_to_end();
+                history_clone.borrow_mut().add(&text);
+            }
+            entry.set_text("");
+            let action = TerminalAction::INPUT(format!("{}\n", text));
+            let _ = ctx.send(Message::Terminal(index, action));
+        });
+
+        let hide_button = self.hide_button.clone();
+        self.entry.connect_key_press_event(move |entry, event| {
+            let line = match event.keyval() {
+                keys::Up => history.borrow_mut().previous(),
+                keys::Down => history.borrow_mut().next(),
+                _ => return Propagation::Proceed,
+            };
+            if !hide_button.is_active() {
+                if let Some(line) = line {
+                    entry.set_text(&line);
+                    entry.set_position(-1);
+                }
+            }
+            Propagation::Stop
         });
+
+        let entry = self.entry.clone();
+        self.hide_button.connect_toggled(move |button| {
+            let hidden = button.is_active();
+            entry.set_visibility(!hidden);
+            entry.set_input_purpose(match hidden {
+                true => InputPurpose::Password,
+                false => InputPurpose::FreeForm,
+            });
+        });
+
+        let ctx = tx.clone();
+        self.eof_button.connect_clicked(move |_| {
+            let _ = ctx.send(Message::Terminal(index, TerminalAction::EOF));
+        });
+    }
+
+    /// Tell the size of the visible text area in characters, without the timestamps,
+    /// which is the terminal size of the program.
+    ///
+    fn connect_resize_event(&self, tx: &Sender<Message>) {
+        let index = self.index;
+        let text_view = self.text_view.clone();
+        let tx = tx.clone();
+        let size = Rc::new(Cell::new((0, 0)));
+        self.scrolled_window
+            .connect_size_allocate(move |_, allocation| {
+                let metrics = text_view.pango_context().metrics(None, None);
+                let char_width = metrics.approximate_char_width() / pango::SCALE;
+                let line_height = (metrics.ascent() + metrics.descent()) / pango::SCALE;
+                if char_width <= 0 || line_height <= 0 {
+                    return;
+                }
+                let columns = (allocation.width() / char_width - TIMESTAMP_WIDTH).max(1) as u16;
+                let rows = (allocation.height() / line_height).max(1) as u16;
+                if size.replace((columns, rows)) != (columns, rows) {
+                    let action = TerminalAction::RESIZE(columns, rows);
+                    let _ = tx.send(Message::Terminal(index, action));
+                }
+            });
+    }
+}
+
+/// Lines entered for the program, browsed from the last one
+///
+#[derive(Default)]
+struct History {
+    lines: Vec<String>,
+    /// Of the browsed line, the length of the lines when none is browsed
+    position: usize,
+}
+
+impl History {
+    fn add(&mut self, line: &str) {
+        if !line.is_empty() && self.lines.last().map(String::as_str) != Some(line) {
+            self.lines.push(line.to_string());
+        }
+        self.position = self.lines.len();
+    }
+
+    fn previous(&mut self) -> Option<String> {
+        self.position = self.position.saturating_sub(1);
+        self.lines.get(self.position).cloned()
+    }
+
+    /// The next line, or the empty one after the last
+    fn next(&mut self) -> Option<String> {
+        self.position = (self.position + 1).min(self.lines.len());
+        Some(self.lines.get(self.position).cloned().unwrap_or_default())
+    }
+}
+
+/// Local time in `HH:MM:SS` format
+///
+fn format_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    DateTime::from_unix_local(secs as i64)
+        .and_then(|dt| dt.format("%H:%M:%S"))
+        .map_or_else(|_| String::new(), |s| s.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn browse_history() {
+        let mut history = History::default();
+        assert_eq!(history.previous(), None);
+
+        for line in ["one", "", "two", "two"] {
+            history.add(line);
+        }
+        assert_eq!(history.previous().as_deref(), Some("two"));
+        assert_eq!(history.previous().as_deref(), Some("one"));
+        assert_eq!(history.previous().as_deref(), Some("one"));
+        assert_eq!(history.next().as_deref(), Some("two"));
+        assert_eq!(history.next().as_deref(), Some(""));
+        assert_eq!(history.next().as_deref(), Some(""));
+
+        history.add("three");
+        assert_eq!(history.previous().as_deref(), Some("three"));
     }
 }
@@ -1,39 +1,94 @@
-use crate::config::Program;
+use crate::config::{Hook, Program};
+use crate::health::HealthEvent;
+use crate::launcher::{HookEvent, ReadyEvent, RestartEvent, StopEvent};
+use crate::stats::Stats;
 use crate::ui::component::{Component, MenuAction, Message, TerminalAction};
 use crate::ui::icons::Icons;
 use gtk::glib::Sender;
 use log::warn;
-use muda::MenuItem;
+use muda::{MenuId, MenuItem, PredefinedMenuItem, Submenu};
 use tray_icon::{
     menu::{Menu, MenuEvent},
     Icon, TrayIcon, TrayIconBuilder,
 };
 
+const STATUS_STOPPED: &str = "Stopped";
+const STATUS_STARTING: &str = "Starting…";
+const STATUS_RUNNING: &str = "Running";
+const STATUS_STOPPING: &str = "Stopping…";
+
+/// Tray icon with the menu of one or several programs
+///
+/// A single program is managed right from the menu,
+/// several programs get a submenu each plus "Start all" and "Stop all".
+///
 #[derive(Clone)]
 pub struct Tray {
     internal: TrayIcon,
     icons: Icons,
-    item_run: MenuItem,  // start/stop program
-    item_show: MenuItem, // show/hide terminal
+    programs: Vec<ProgramMenu>,
+    item_start_all: Option<MenuItem>,
+    item_stop_all: Option<MenuItem>,
     item_quit: MenuItem,
+}
+
+/// Menu items and state of a single program
+///
+#[derive(Clone)]
+struct ProgramMenu {
+    title: String,
+    wait_ready: bool,         // the program prints the ready line after the start
+    submenu: Option<Submenu>, // titled with the program status, if several programs
+    item_status: MenuItem,    // program state, not clickable
+    item_run: MenuItem,       // start/stop program
+    item_show: MenuItem,      // show/hide terminal
+    status: String,
+    stats: Option<Stats>, // of the running program
+    state: IconState,
     is_running: bool,
     is_shown: bool,
 }
 
+/// State of the program shown by the tray icon, the worst one wins
+///
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum IconState {
+    Off,
+    On,
+    Starting,
+    Unhealthy,
+}
+
 impl Component for Tray {
     fn start(&mut self, tx: &Sender<Message>) {
         let rx = MenuEvent::receiver();
         let tx = tx.clone();
-        let run_id = self.item_run.id().clone();
-        let show_id = self.item_show.id().clone();
+        let run_ids: Vec<MenuId> = self
+            .programs
+            .iter()
+            .map(|p| p.item_run.id().clone())
+            .collect();
+        let show_ids: Vec<MenuId> = self
+            .programs
+            .iter()
+            .map(|p| p.item_show.id().clone())
+            .collect();
+        let start_all_id = self.item_start_all.as_ref().map(|item| item.id().clone());
+        let stop_all_id = self.item_stop_all.as_ref().map(|item| item.id().clone());
         let quit_id = self.item_quit.id().clone();
         std::thread::spawn(move || {
             while let Ok(event) = rx.recv() {
-                let action = match event.id {
-                    id if id == run_id => MenuAction::RUN,
-                    id if id == show_id => MenuAction::VISIBILITY,
-                    id if id == quit_id => MenuAction::QUIT,
-                    _ => MenuAction::UNKNOWN(event.id),
+                let action = if let Some(index) = run_ids.iter().position(|id| *id == event.id) {
+                    MenuAction::RUN(index)
+                } else if let Some(index) = show_ids.iter().position(|id| *id == event.id) {
+                    MenuAction::VISIBILITY(index)
+                } else {
+                    match event.id {
+                        id if Some(&id) == start_all_id.as_ref() => MenuAction::START_ALL,
+                        id if Some(&id) == stop_all_id.as_ref() => MenuAction::STOP_ALL,
+                        id if id == quit_id => MenuAction::QUIT,
+                        _ => MenuAction::UNKNOWN(event.id),
+                    }
                 };
                 let _ = tx.send(Message::TrayMenu(action));
             }
@@ -43,27 +98,64 @@ impl Component for Tray {
     fn on_message_received(&mut self, msg: &Message) {
         match msg {
             Message::TrayMenu(action) => self.on_action_selected(action),
-            Message::Terminal(action) => self.on_terminal_action(action),
-            Message::ProgramStopped(_) => self.on_program_stopped(),
-            Message::ProgramOutput(_) => {}
+            Message::Terminal(i, action) => self.programs[*i].on_terminal_action(action),
+            Message::ProgramStarted(i) => self.programs[*i].on_program_started(),
+            Message::ProgramStopping(i, event) => self.programs[*i].on_program_stopping(event),
+            Message::ProgramStopped(i, _) => self.programs[*i].on_program_stopped(),
+            Message::ProgramRestart(i, event) => self.programs[*i].on_program_restart(event),
+            Message::ProgramReady(i, event) => self.programs[*i].on_program_ready(event),
+            Message::ProgramHealth(i, event) => self.programs[*i].on_program_health(event),
+            Message::ProgramHook(i, event) => self.programs[*i].on_program_hook(event),
+            Message::ProgramStats(i, stats) => {
+                // the last sample may come after the exit
+                if self.programs[*i].is_running {
+                    self.programs[*i].stats = Some(stats.clone());
+                    self.update_tooltip();
+                }
+                return;
+            }
+            Message::ProgramOutput(_, _) => return,
         }
+        self.update();
     }
 }
 
 impl Tray {
-    pub fn new(program: &Program, icons: &Icons) -> Self {
+    pub fn new(programs: &[Program], icons: &Icons) -> Self {
         let tray_menu = Menu::new();
-        let item_run = MenuItem::new("Start", true, None);
-        tray_menu.append(&item_run).unwrap();
-        let item_show = MenuItem::new("Show", true, None);
-        tray_menu.append(&item_show).unwrap();
+        let several = programs.len() > 1;
+        let programs: Vec<ProgramMenu> = programs
+            .iter()
+            .map(|program| ProgramMenu::new(program, several))
+            .collect();
+        for program in programs.iter() {
+            match program.submenu.as_ref() {
+                Some(submenu) => tray_menu.append(submenu).unwrap(),
+                None => tray_menu
+                    .append_items(&[&program.item_status, &program.item_run, &program.item_show])
+                    .unwrap(),
+            }
+        }
+
+        let (item_start_all, item_stop_all) = match several {
+            true => {
+                let item_start_all = MenuItem::new("Start all", true, None);
+                let item_stop_all = MenuItem::new("Stop all", true, None);
+                tray_menu.append(&PredefinedMenuItem::separator()).unwrap();
+                tray_menu.append(&item_start_all).unwrap();
+                tray_menu.append(&item_stop_all).unwrap();
+                tray_menu.append(&PredefinedMenuItem::separator()).unwrap();
+                (Some(item_start_all), Some(item_stop_all))
+            }
+            false => (None, None),
+        };
         let item_quit = MenuItem::new("Quit", true, None);
         tray_menu.append(&item_quit).unwrap();
 
         let icons = icons.clone();
         let internal = TrayIconBuilder::new()
             .with_icon(icons.off.clone())
-            .with_tooltip(program.get_title())
+            .with_tooltip(tooltip(&programs))
             .with_menu(Box::new(tray_menu))
             .build()
             .expect("Failed to create tray icon");
@@ -71,53 +163,202 @@ impl Tray {
         Self {
             internal,
             icons,
-            item_run,
-            item_show,
+            programs,
+            item_start_all,
+            item_stop_all,
             item_quit,
-            is_running: false,
-            is_shown: false,
         }
     }
 
     fn on_action_selected(&mut self, action: &MenuAction) {
         match action {
-            MenuAction::RUN => self.toggle_running(),
-            MenuAction::VISIBILITY => self.toggle_terminal_visibility(),
+            MenuAction::RUN(index) => self.programs[*index].toggle_running(),
+            MenuAction::VISIBILITY(index) => self.programs[*index].toggle_terminal_visibility(),
+            MenuAction::START_ALL => {} // waiting for programs start...
+            MenuAction::STOP_ALL => self
+                .programs
+                .iter_mut()
+                .filter(|program| program.is_running)
+                .for_each(|program| program.toggle_running()),
             MenuAction::QUIT => gtk::main_quit(),
             MenuAction::UNKNOWN(menu_id) => warn!("unknown menu action: {:?}", menu_id),
         }
     }
 
+    /// Show the state of all programs by the icon and the tooltip.
+    ///
+    fn update(&self) {
+        let state = self.programs.iter().map(|program| program.state).max();
+        let icon = match state.unwrap_or(IconState::Off) {
+            IconState::Off => &self.icons.off,
+            IconState::On => &self.icons.on,
+            IconState::Starting => &self.icons.starting,
+            IconState::Unhealthy => &self.icons.unhealthy,
+        };
+        self.set_icon(icon);
+        self.update_tooltip();
+    }
+
+    fn update_tooltip(&self) {
+        let _ = self.internal.set_tooltip(Some(tooltip(&self.programs)));
+    }
+
+    fn set_icon(&self, icon: &Icon) {
+        self.internal.set_icon(Some(icon.clone())).unwrap(); // TODO: unwrap
+    }
+}
+
+impl ProgramMenu {
+    fn new(program: &Program, with_submenu: bool) -> Self {
+        let title = program.get_title().to_string();
+        let item_status = MenuItem::new(STATUS_STOPPED, false, None);
+        let item_run = MenuItem::new("Start", true, None);
+        let item_show = MenuItem::new("Show", true, None);
+        let submenu = with_submenu.then(|| {
+            let text = format!("{}: {}", title, STATUS_STOPPED);
+            let submenu = Submenu::with_items(text, true, &[&item_status, &item_run, &item_show]);
+            submenu.expect("Failed to create program menu")
+        });
+        Self {
+            title,
+            wait_ready: program.get_ready_pattern().is_some(),
+            submenu,
+            item_status,
+            item_run,
+            item_show,
+            status: STATUS_STOPPED.to_string(),
+            stats: None,
+            state: IconState::Off,
+            is_running: false,
+            is_shown: false,
+        }
+    }
+
     fn on_terminal_action(&mut self, action: &TerminalAction) {
-        self.switch_terminal_visibility(match action {
-            TerminalAction::HIDE => false,
-        })
+        match action {
+            TerminalAction::HIDE => self.switch_terminal_visibility(false),
+            TerminalAction::RESIZE(..) | TerminalAction::INPUT(_) | TerminalAction::EOF => {}
+        }
     }
 
     fn toggle_running(&mut self) {
         if self.is_running {
             self.item_run.set_enabled(false);
             // waiting for program stop...
-        } else {
-            self.on_program_started();
         }
+        // otherwise waiting for program start with its dependencies...
     }
 
     fn on_program_started(&mut self) {
         self.item_run.set_text("Stop");
-        self.set_icon(&self.icons.on);
+        self.state = match self.wait_ready {
+            true => IconState::Starting,
+            false => IconState::On,
+        };
+        self.set_status(self.started_status());
         self.is_running = true;
     }
 
+    fn started_status(&self) -> &'static str {
+        match self.wait_ready {
+            true => STATUS_STARTING,
+            false => STATUS_RUNNING,
+        }
+    }
+
+    fn on_program_ready(&mut self, event: &ReadyEvent) {
+        match event {
+            ReadyEvent::Ready { .. } => {
+                self.state = IconState::On;
+                self.set_status(STATUS_RUNNING);
+            }
+            ReadyEvent::TimedOut { timeout } => {
+                self.set_status(&format!("Not ready after {:.1}s", timeout.as_secs_f64()))
+            }
+        }
+    }
+
     fn on_program_stopped(&mut self) {
         self.item_run.set_text("Start");
         self.item_run.set_enabled(true);
-        self.set_icon(&self.icons.off);
+        self.state = IconState::Off;
+        self.set_status(STATUS_STOPPED);
+        self.stats = None;
         self.is_running = false;
     }
 
-    fn set_icon(&self, icon: &Icon) {
-        self.internal.set_icon(Some(icon.clone())).unwrap(); // TODO: unwrap
+    fn on_program_stopping(&mut self, event: &StopEvent) {
+        self.item_run.set_enabled(false);
+        match event {
+            StopEvent::Signalled(_) => self.set_status(STATUS_STOPPING),
+            StopEvent::Escalated { signal, .. } => {
+                self.set_status(&format!("{} ({})", STATUS_STOPPING, signal))
+            }
+        }
+    }
+
+    fn on_program_restart(&mut self, event: &RestartEvent) {
+        match event {
+            RestartEvent::Scheduled {
+                attempt,
+                max_retries,
+                ..
+            } => {
+                // the program is stopped, but "Stop" cancels the pending restart
+                self.item_run.set_text("Stop");
+                self.is_running = true;
+                self.set_status(&format!("Restarting ({}/{})", attempt, max_retries));
+            }
+            RestartEvent::Restarted {
+                attempt,
+                max_retries,
+            } => {
+                self.on_program_started();
+                let status = self.started_status();
+                self.set_status(&format!("{} (restart {}/{})", status, attempt, max_retries));
+            }
+            RestartEvent::Cancelled => self.on_program_stopped(),
+            RestartEvent::GaveUp { retries } => {
+                self.on_program_stopped();
+                self.set_status(&format!("Gave up after {} retries", retries));
+            }
+        }
+    }
+
+    fn on_program_health(&mut self, event: &HealthEvent) {
+        if !self.is_running {
+            return;
+        }
+        match event {
+            HealthEvent::Healthy => {
+                self.state = IconState::On;
+                self.set_status(STATUS_RUNNING);
+            }
+            HealthEvent::Unhealthy { reason } => {
+                self.state = IconState::Unhealthy;
+                self.set_status(&format!("Unhealthy: {}", reason));
+            }
+        }
+    }
+
+    fn on_program_hook(&mut self, event: &HookEvent) {
+        // the program is not started after the failed pre_start hook
+        if let HookEvent::Failed {
+            hook: Hook::PreStart,
+            ..
+        } = event
+        {
+            self.on_program_stopped();
+            self.set_status(&format!("{} failed", Hook::PreStart));
+        }
+    }
+
+    fn set_status(&mut self, status: &str) {
+        self.item_status.set_text(status);
+        if let Some(submenu) = self.submenu.as_ref() {
+            submenu.set_text(format!("{}: {}", self.title, status));
+        }
+        self.status = status.to_string();
     }
 
     fn toggle_terminal_visibility(&mut self) {
@@ -133,3 +374,16 @@ impl Tray {
         self.is_shown = visible;
     }
 }
+
+/// Status line of every program, followed by the resource usage of the running one.
+///
+fn tooltip(programs: &[ProgramMenu]) -> String {
+    programs
+        .iter()
+        .map(|program| match program.stats.as_ref() {
+            Some(stats) => format!("{}: {}\n{}", program.title, program.status, stats),
+            None => format!("{}: {}", program.title, program.status),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}